@@ -0,0 +1,53 @@
+use crate::tracer::PhantomEvent;
+use regex::Regex;
+use std::sync::LazyLock;
+
+// ESC [ parameters intermediates final, e.g. `\x1b[1;31m` (SGR) or `\x1b[2K`
+static CSI_SEQUENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]").unwrap());
+
+/// Remove CSI escape sequences from `line`, returning the plain text and each removed
+/// sequence with its byte offset in the plain text
+pub fn strip(line: &str) -> (String, Vec<(usize, &str)>) {
+    let mut plain = String::with_capacity(line.len());
+    let mut sequences = Vec::new();
+    let mut cursor = 0;
+    for sequence in CSI_SEQUENCE.find_iter(line) {
+        plain.push_str(&line[cursor..sequence.start()]);
+        sequences.push((plain.len(), sequence.as_str()));
+        cursor = sequence.end();
+    }
+    plain.push_str(&line[cursor..]);
+    (plain, sequences)
+}
+
+/// Apply `events` (positions in `plain`, in order) to `plain`, putting the stripped
+/// sequences back. Sequences outside a match return to their original place; those
+/// inside one are emitted just before its phantom value, so the styling still applies.
+pub fn reinsert(plain: &str, events: &[PhantomEvent], sequences: &[(usize, &str)]) -> String {
+    let mut output = String::with_capacity(plain.len());
+    let mut sequences = sequences.iter().peekable();
+    let mut cursor = 0;
+
+    for event in events {
+        let (start, end) = event.position;
+        while let Some((offset, sequence)) = sequences.next_if(|(offset, _)| *offset <= start) {
+            output.push_str(&plain[cursor..*offset]);
+            output.push_str(sequence);
+            cursor = *offset;
+        }
+        output.push_str(&plain[cursor..start]);
+        while let Some((_, sequence)) = sequences.next_if(|(offset, _)| *offset < end) {
+            output.push_str(sequence);
+        }
+        output.push_str(&event.phantom_value);
+        cursor = end;
+    }
+    for (offset, sequence) in sequences {
+        output.push_str(&plain[cursor..*offset]);
+        output.push_str(sequence);
+        cursor = *offset;
+    }
+    output.push_str(&plain[cursor..]);
+    output
+}
@@ -0,0 +1,202 @@
+use crate::config::{PhantomTraceConfig, TcpFraming};
+use crate::error::PhantomError;
+use crate::processor::PhantomTraceProcessor;
+use crate::rate_limit::{Admission, RateLimiter};
+use crate::tcp_server::SHUTDOWN_GRACE_PERIOD;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+
+// Connections are mostly idle agents, so each pool worker can carry many of them
+const CONNECTIONS_PER_WORKER: usize = 256;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The multi-threaded runtime the async server runs on. Timers are enabled along with
+/// I/O: a rate-limited connection sleeps under `RateLimitPolicy::Block`.
+pub fn runtime(worker_threads: usize) -> std::io::Result<tokio::runtime::Runtime> {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(worker_threads.max(1))
+        .enable_all()
+        .build()
+}
+
+/// Serve the phantom protocol (line-oriented, or as `preprocessing.tcp_framing` says)
+/// on `0.0.0.0:port` using Tokio tasks
+/// instead of one OS thread per connection, until `shutdown` is set
+pub async fn serve_tcp_async(
+    config: PhantomTraceConfig,
+    port: u16,
+    shutdown: Arc<AtomicBool>,
+) -> Result<(), PhantomError> {
+    let listener = TcpListener::bind(("0.0.0.0", port)).await?;
+    eprintln!("PhantomTrace async TCP server listening on port {}", port);
+    serve_listener(config, listener, shutdown).await
+}
+
+/// Accept connections from an already bound listener. At most
+/// `thread_pool_size * 256` clients are served at once; further clients wait
+/// in the accept backlog until a slot frees up. Once `shutdown` is set the listener
+/// is closed and connected clients get `SHUTDOWN_GRACE_PERIOD` to finish.
+pub async fn serve_listener(
+    config: PhantomTraceConfig,
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+) -> Result<(), PhantomError> {
+    // Rules are compiled once; every connection gets a cheap session of this processor
+    let template = PhantomTraceProcessor::new(config)?;
+    serve_processor(template, listener, shutdown).await
+}
+
+/// `serve_listener` with connections served by sessions of `template`, e.g. one
+/// given a stats sink for the health server
+pub async fn serve_processor(
+    template: PhantomTraceProcessor,
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+) -> Result<(), PhantomError> {
+    let config = &template.config;
+    let max_connections = config
+        .preprocessing
+        .performance_tuning
+        .thread_pool_size
+        .max(1)
+        * CONNECTIONS_PER_WORKER;
+    let slots = Arc::new(Semaphore::new(max_connections));
+    let tuning = config.preprocessing.performance_tuning.clone();
+    let framing = config.preprocessing.tcp_framing;
+
+    loop {
+        let next_client = async {
+            let permit = slots
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("connection semaphore is never closed");
+            (permit, listener.accept().await)
+        };
+        let (permit, accepted) = tokio::select! {
+            next_client = next_client => next_client,
+            _ = shutdown_requested(&shutdown) => break,
+        };
+        let stream = match accepted {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                eprintln!("Connection failed: {}", e);
+                continue;
+            }
+        };
+
+        let processor = template.session();
+        let limiter = RateLimiter::from_config(&tuning);
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, processor, limiter, framing).await {
+                eprintln!("Client error: {}", e);
+            }
+            drop(permit);
+        });
+    }
+
+    drop(listener);
+    // Every slot is free again once the last client is done
+    let all_slots = u32::try_from(max_connections).unwrap_or(u32::MAX);
+    let drained = tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, slots.acquire_many(all_slots)).await;
+    if drained.is_err() {
+        eprintln!(
+            "Grace period elapsed with {} client(s) still connected",
+            max_connections - slots.available_permits()
+        );
+    }
+    Ok(())
+}
+
+// Resolves once `shutdown` is set; the flag comes from a signal handler, so it's polled
+async fn shutdown_requested(shutdown: &AtomicBool) {
+    while !shutdown.load(Ordering::Relaxed) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    mut processor: PhantomTraceProcessor,
+    mut limiter: Option<RateLimiter>,
+    framing: TcpFraming,
+) -> Result<(), PhantomError> {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+    let (read_half, mut write_half) = stream.into_split();
+    let mut reader = BufReader::new(read_half);
+
+    'records: while let Some(record) = read_record(&mut reader, framing, &processor).await? {
+        while let Some(limiter) = &mut limiter {
+            match limiter.check(&peer) {
+                Admission::Allow => break,
+                Admission::Wait(delay) => tokio::time::sleep(delay).await,
+                Admission::Drop => continue 'records,
+            }
+        }
+        let result = processor.phantom_text(&record);
+        let payload = result.phantomed_text.as_bytes();
+        match framing {
+            TcpFraming::Line => {
+                write_half.write_all(payload).await?;
+                write_half.write_all(b"\n").await?;
+            }
+            TcpFraming::LengthPrefixed => {
+                let len = u32::try_from(payload.len())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+                write_half.write_all(&len.to_be_bytes()).await?;
+                write_half.write_all(payload).await?;
+            }
+        }
+    }
+
+    write_half.shutdown().await?;
+    Ok(())
+}
+
+// The next record from the client, or `None` once it has closed its side
+async fn read_record(
+    reader: &mut BufReader<OwnedReadHalf>,
+    framing: TcpFraming,
+    processor: &PhantomTraceProcessor,
+) -> Result<Option<String>, PhantomError> {
+    match framing {
+        TcpFraming::Line => {
+            let mut raw_line = Vec::new();
+            if reader.read_until(b'\n', &mut raw_line).await? == 0 {
+                return Ok(None);
+            }
+            let line = processor.decode_line(&raw_line)?;
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()))
+        }
+        TcpFraming::LengthPrefixed => {
+            if reader.fill_buf().await?.is_empty() {
+                return Ok(None);
+            }
+            let len = reader.read_u32().await? as usize;
+            let max_frame_bytes = processor.config.preprocessing.max_frame_bytes;
+            if len > max_frame_bytes {
+                let message = format!("frame of {} bytes exceeds {}", len, max_frame_bytes);
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message).into());
+            }
+            // Grown as bytes arrive, so a header alone can't make us allocate `len`
+            let mut payload = Vec::new();
+            (&mut *reader)
+                .take(len as u64)
+                .read_to_end(&mut payload)
+                .await?;
+            if payload.len() < len {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            Ok(Some(processor.decode_line(&payload)?.into_owned()))
+        }
+    }
+}
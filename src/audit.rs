@@ -0,0 +1,82 @@
+use crate::error::PhantomError;
+use crate::tracer::PhantomEvent;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Append-only JSON-lines record of phantom events. Original values are never written;
+/// with a salt configured, a salted SHA-256 of each original allows correlation.
+/// Clones share one file handle, so concurrent sessions append whole lines.
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    file: Arc<Mutex<File>>,
+    hash_salt: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    rule: &'a str,
+    severity: String,
+    trace_id: &'a str,
+    position: (usize, usize),
+    #[serde(skip_serializing_if = "Option::is_none")]
+    field_path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    original_hash: Option<String>,
+}
+
+impl AuditLog {
+    pub fn open<P: AsRef<Path>>(path: P, hash_salt: Option<String>) -> Result<Self, PhantomError> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Arc::new(Mutex::new(file)),
+            hash_salt,
+        })
+    }
+
+    pub fn record(&self, events: &[PhantomEvent]) -> Result<(), PhantomError> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let timestamp = humantime::format_rfc3339_millis(crate::clock::now()).to_string();
+        let mut lines = String::new();
+        for event in events {
+            let record = AuditRecord {
+                timestamp: timestamp.clone(),
+                rule: &event.rule_name,
+                severity: format!("{:?}", event.severity),
+                trace_id: &event.trace_id,
+                position: event.position,
+                field_path: event.field_path.as_deref(),
+                original_hash: self
+                    .hash_salt
+                    .as_ref()
+                    .map(|salt| salted_hash(salt, &event.original_value)),
+            };
+            lines.push_str(&serde_json::to_string(&record)?);
+            lines.push('\n');
+        }
+
+        // One write per batch under the lock keeps lines from interleaving
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+pub(crate) fn salted_hash(salt: &str, value: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update([0]);
+    hasher.update(value.as_bytes());
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
@@ -0,0 +1,169 @@
+use crate::clock::Instant;
+use crate::config::PhantomTraceConfig;
+use crate::error::PhantomError;
+use crate::processor::{PhantomTraceProcessor, ProcessingStatsOutput};
+use crate::tracer::PhantomTracer;
+use std::fmt;
+use std::time::Duration;
+
+// Lines handed to the processor per call, so per-call overhead doesn't dominate
+const CHUNK_LINES: usize = 1000;
+
+/// When a benchmark run stops: after `lines` lines or `duration`, whichever comes first.
+/// With neither set the corpus is processed once.
+#[derive(Debug, Clone)]
+pub struct BenchmarkLimit {
+    pub lines: Option<usize>,
+    pub duration: Option<Duration>,
+}
+
+impl Default for BenchmarkLimit {
+    fn default() -> Self {
+        Self {
+            lines: Some(100_000),
+            duration: None,
+        }
+    }
+}
+
+/// Time one rule spent on a single pass over the corpus, run on its own
+#[derive(Debug, Clone)]
+pub struct RuleTiming {
+    pub rule_name: String,
+    pub duration: Duration,
+    pub events: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkReport {
+    pub stats: ProcessingStatsOutput,
+    pub elapsed: Duration,
+    pub rules: Vec<RuleTiming>,
+}
+
+/// `lines` synthetic log lines mixing clean text with card numbers, emails, IPs,
+/// phone numbers and secrets, in a fixed rotation so runs are comparable
+pub fn synthetic_corpus(lines: usize) -> Vec<String> {
+    (0..lines)
+        .map(|i| match i % 6 {
+            0 => format!(
+                "2024-01-01T12:00:{:02}Z INFO request {} completed in 12ms",
+                i % 60,
+                i
+            ),
+            1 => format!(
+                "payment accepted card=4532 0151 1283 {:04} amount=19.99",
+                i % 10_000
+            ),
+            2 => format!(
+                "login ok user=user{}@example.com from 10.0.{}.{}",
+                i,
+                i % 256,
+                i % 200
+            ),
+            3 => format!("callback requested phone=(555) 123-{:04}", i % 10_000),
+            4 => format!(
+                "config reloaded api_key=sk_live_{:024} password=hunter{}",
+                i, i
+            ),
+            _ => format!(
+                "worker {} heartbeat queue_depth={} status=healthy",
+                i % 16,
+                i % 97
+            ),
+        })
+        .collect()
+}
+
+/// Run `corpus` (repeated as needed) through the configured rules until `limit` is
+/// reached, then time each active rule on its own over one pass of the corpus
+pub fn run_benchmark(
+    config: &PhantomTraceConfig,
+    corpus: &[String],
+    limit: &BenchmarkLimit,
+) -> Result<BenchmarkReport, PhantomError> {
+    if corpus.is_empty() {
+        return Err(PhantomError::Config("Benchmark corpus is empty".into()));
+    }
+    let mut processor = PhantomTraceProcessor::new(config.clone())?;
+
+    let start = Instant::now();
+    let mut lines_done = 0;
+    'run: loop {
+        for chunk in corpus.chunks(CHUNK_LINES) {
+            let remaining = limit.lines.map_or(chunk.len(), |max| max - lines_done);
+            let chunk = &chunk[..chunk.len().min(remaining)];
+            processor.phantom_text(&chunk.join("\n"));
+            // `join` drops the separators a real file would carry
+            processor.record_extra_bytes(chunk.len());
+            lines_done += chunk.len();
+
+            let lines_reached = limit.lines.is_some_and(|max| lines_done >= max);
+            let time_reached = limit.duration.is_some_and(|max| start.elapsed() >= max);
+            if lines_reached || time_reached || limit.lines.is_none() && limit.duration.is_none() {
+                break 'run;
+            }
+        }
+    }
+    let elapsed = start.elapsed();
+    let stats = processor.get_processing_stats();
+
+    let mut rules = Vec::new();
+    for rule in config.effective_rules() {
+        if !rule.enabled || rule.severity < config.processing.min_severity {
+            continue;
+        }
+        let mut tracer = PhantomTracer::with_limits(
+            std::slice::from_ref(&rule),
+            config.tracing.case_sensitive,
+            config.tracing.unicode_boundaries,
+            &config.tracing.regex_limits(),
+        )?;
+        let rule_start = Instant::now();
+        let mut events = 0;
+        for line in corpus {
+            events += tracer.trace_and_phantom(line).1.len() as u64;
+        }
+        rules.push(RuleTiming {
+            rule_name: rule.name,
+            duration: rule_start.elapsed(),
+            events,
+        });
+    }
+    rules.sort_by_key(|rule| std::cmp::Reverse(rule.duration));
+
+    Ok(BenchmarkReport {
+        stats,
+        elapsed,
+        rules,
+    })
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Lines processed: {}", self.stats.lines_processed)?;
+        writeln!(f, "Bytes processed: {}", self.stats.bytes_processed)?;
+        writeln!(f, "Events: {}", self.stats.total_phantom_events)?;
+        writeln!(f, "Elapsed: {:?}", self.elapsed)?;
+        writeln!(f, "Lines/sec: {:.0}", self.stats.lines_per_second)?;
+        writeln!(f, "MB/sec: {:.2}", self.stats.mb_per_second)?;
+        writeln!(
+            f,
+            "Per-rule time (each rule alone, one pass over the corpus):"
+        )?;
+        let name_width = self
+            .rules
+            .iter()
+            .map(|rule| rule.rule_name.len())
+            .max()
+            .unwrap_or(0);
+        for rule in &self.rules {
+            writeln!(
+                f,
+                "  {:<name_width$}  {:>12?}  {} events",
+                rule.rule_name, rule.duration, rule.events
+            )?;
+        }
+        Ok(())
+    }
+}
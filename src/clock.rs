@@ -0,0 +1,67 @@
+//! Clocks that also work on wasm32-unknown-unknown, where `std::time::SystemTime::now`
+//! and `Instant::now` panic
+
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::Instant;
+
+/// Wall-clock time; from JavaScript's `Date.now()` in the browser
+pub(crate) fn now() -> SystemTime {
+    #[cfg(target_arch = "wasm32")]
+    {
+        use web_time::web::SystemTimeExt;
+        web_time::SystemTime::now().to_std()
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        SystemTime::now()
+    }
+}
+
+/// Where tracers read wall-clock time from: trace IDs, `TraceStats` first/last
+/// trace and `TraceReport::generation_time`
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> SystemTime;
+}
+
+/// The real clock, used unless another is installed
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        now()
+    }
+}
+
+/// A clock that stands still until `set` or `advance` moves it, for tests
+#[derive(Debug)]
+pub struct MockClock {
+    time: Mutex<SystemTime>,
+}
+
+impl MockClock {
+    pub fn new(start: SystemTime) -> Self {
+        Self {
+            time: Mutex::new(start),
+        }
+    }
+
+    pub fn set(&self, time: SystemTime) {
+        *self.time.lock().unwrap_or_else(|e| e.into_inner()) = time;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.time.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.time.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -1,8 +1,14 @@
+use crate::error::PhantomError;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use std::time::Duration;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Names accepted by `PhantomTraceConfig::from_preset`
+pub const PRESET_NAMES: [&str; 5] = ["default", "splunk", "elk", "high-performance", "strict-pci"];
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PhantomTraceConfig {
     pub tracing: TracingConfig,
     pub processing: ProcessingConfig,
@@ -11,34 +17,199 @@ pub struct PhantomTraceConfig {
     pub monitoring: MonitoringConfig, // Added missing monitoring section
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TracingConfig {
     pub enabled: bool,
     pub rules: Vec<TraceRule>,
     pub custom_patterns: Vec<CustomPattern>,
     pub case_sensitive: bool,
+    #[serde(default)]
+    pub allowlist: Vec<String>, // Exact values that are never obfuscated
+    #[serde(default)]
+    pub allowlist_patterns: Vec<String>, // Regexes that must match the whole value
+    // `\b`/`\B` semantics. The regex crate's default is Unicode-aware: `é` or `Ж` count as
+    // word characters, so `tél5551234567` has no boundary before the digits and no match.
+    // `false` switches to ASCII boundaries, where any non-ASCII letter ends a word: more
+    // matches next to accented text, but also inside words like `naïve`.
+    #[serde(default = "default_unicode_boundaries")]
+    pub unicode_boundaries: bool,
+    #[serde(default)]
+    pub redact_keys: Vec<String>, // JSON/logfmt keys (full-match regexes) whose whole value is obfuscated
+    #[serde(default = "default_redact_method")]
+    pub redact_method: ObfuscationMethod,
+    #[serde(default)]
+    pub locales: Vec<String>, // Extra government ID rules, e.g. "US" or just "US-CA"; see `locale_trace_rules`
+    #[serde(default)]
+    pub rule_packs: Vec<String>, // Bundled pack names ("pci", "hipaa", "gdpr") or rule pack file paths
+    #[serde(default = "default_severity_methods")]
+    pub default_methods: HashMap<TraceSeverity, ObfuscationMethod>, // For rules without a `method`
+    // Bytes a rule's compiled program and lazy DFA cache may use, so a pattern like
+    // `(\w{100}){100}` is rejected when loading instead of exhausting memory
+    #[serde(default = "default_regex_size_limit")]
+    pub regex_size_limit: usize,
+    #[serde(default = "default_dfa_size_limit")]
+    pub dfa_size_limit: usize,
+}
+
+/// Contents of a rule pack file: `{"name": "...", "rules": [...]}`
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RulePack {
+    #[serde(default)]
+    pub name: String,
+    pub rules: Vec<TraceRule>,
+}
+
+/// Name, severity, method and effective on/off state of one rule
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleSummary {
+    pub name: String,
+    pub severity: TraceSeverity,
+    pub method: ObfuscationMethod,
+    pub enabled: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct TraceRule {
+    #[serde(default = "default_rule_enabled")]
+    pub enabled: bool,
     pub name: String,
     pub pattern: String,
-    pub method: ObfuscationMethod,
+    pub method: Option<ObfuscationMethod>, // Omitted: `tracing.default_methods` for the severity
     pub preserve_chars: Option<usize>,
     pub replacement: Option<String>,
     pub severity: TraceSeverity,
+    #[serde(default)]
+    pub priority: i32, // Tiebreaker within a severity: higher wins contested spans
+    pub min_length: Option<usize>, // Bounds on the matched value, in characters
+    pub max_length: Option<usize>,
+    #[serde(default)]
+    pub context_keywords: Vec<String>, // If set, one must precede the match within the window
+    #[serde(default = "default_context_window")]
+    pub context_window: usize, // Characters before the match searched for a keyword
+    #[serde(default)]
+    pub trim_match: bool, // Keep surrounding whitespace and `trim_chars` out of obfuscation
+    #[serde(default = "default_trim_chars")]
+    pub trim_chars: String,
+    pub token_format: Option<String>, // Mirror/Tokenize output, e.g. "SSN_{hash}"; `{rule}` also works
+    pub mask_char: Option<char>, // This rule's masking glyph, instead of `processing.mask_char`
+    #[serde(default)]
+    pub obfuscate_groups: Vec<String>, // If set, only these named groups are obfuscated; the rest is kept
+    // Fit Mirror tokens to the original's character length for fixed-width formats.
+    // Short values keep only the token's tail, so distinct equal-length inputs can
+    // look alike even though their hashes (and trace map entries) stay distinct.
+    #[serde(default)]
+    pub mirror_preserve_length: bool,
+    pub validator: Option<MatchValidator>, // Checksum a match must pass to count as high-confidence
+    #[serde(default)]
+    pub anchored: bool, // Only fire when the pattern matches the entire line (or JSON/CSV/logfmt value)
+    // Reuse the first phantom output for a value for the rest of the run (until stats are
+    // reset), whatever the method, template placeholders or obfuscation hook would give.
+    // Up to 65,536 values are remembered; past that the oldest may get a new output.
+    #[serde(default)]
+    pub consistent: bool,
+    #[serde(default)]
+    pub tags: Vec<String>, // With active tags (`phantom_text_tagged`), runs only if one is active
+    // Rules that must all have matched in the same line for this one to apply, e.g. `cvv`
+    // only alongside `credit_card`. The scope is a whole document in `document_mode`, and
+    // a single value for JSON/CSV/logfmt-aware fields.
+    #[serde(default)]
+    pub requires: Vec<String>,
+}
+
+impl Default for TraceRule {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            name: String::new(),
+            pattern: String::new(),
+            method: Some(ObfuscationMethod::Phantom),
+            preserve_chars: None,
+            replacement: None,
+            severity: TraceSeverity::Medium,
+            priority: 0,
+            min_length: None,
+            max_length: None,
+            context_keywords: Vec::new(),
+            context_window: default_context_window(),
+            trim_match: false,
+            trim_chars: default_trim_chars(),
+            token_format: None,
+            mask_char: None,
+            obfuscate_groups: Vec::new(),
+            mirror_preserve_length: false,
+            validator: None,
+            anchored: false,
+            consistent: false,
+            tags: Vec::new(),
+            requires: Vec::new(),
+        }
+    }
+}
+
+/// Check applied to a match to tell real values from look-alikes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum MatchValidator {
+    Luhn, // Card number checksum; separators are ignored
+    // Digits must include at least `min_unique` distinct values and not run in sequence
+    // (`12345678`, `98765432`, wrapping past 0). Unlike Luhn, a failing match is skipped.
+    Entropy { min_unique: usize },
+}
+
+impl MatchValidator {
+    /// Whether a failing match is left untouched, rather than only scored lower
+    pub fn rejects(&self, value: &str) -> bool {
+        match self {
+            MatchValidator::Luhn => false,
+            MatchValidator::Entropy { .. } => !self.is_valid(value),
+        }
+    }
+
+    pub fn is_valid(&self, value: &str) -> bool {
+        match self {
+            MatchValidator::Entropy { min_unique } => {
+                let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+                let unique = digits.iter().collect::<HashSet<_>>().len();
+                let steps: HashSet<u32> = digits
+                    .windows(2)
+                    .map(|pair| (pair[1] + 10 - pair[0]) % 10)
+                    .collect();
+                let sequential = steps.len() == 1 && (steps.contains(&1) || steps.contains(&9));
+                unique >= *min_unique && !sequential
+            }
+            MatchValidator::Luhn => {
+                let digits: Vec<u32> = value.chars().filter_map(|c| c.to_digit(10)).collect();
+                if digits.len() < 2 {
+                    return false;
+                }
+                let sum: u32 = digits
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(index, &digit)| match index % 2 {
+                        0 => digit,
+                        _ if digit * 2 > 9 => digit * 2 - 9,
+                        _ => digit * 2,
+                    })
+                    .sum();
+                sum.is_multiple_of(10)
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum ObfuscationMethod {
     Phantom,  // Replace with phantom characters (****)
-    Vanish,   // Remove entirely
+    Vanish,   // Remove entirely, or leave `replacement` as a marker (`{mask:len}` keeps the width)
     Mirror,   // Replace with hash/token
     Mask,     // Replace with custom string
     Tokenize, // Replace with traceable token
+    Phone,    // Normalize to (***) ***-1234, keeping the real last 4 digits
+    Card,     // Mask all but the last `preserve_chars` digits (default 4), keeping separators
+    Detect,   // Leave the value as is but still emit an event, for audit-only rules
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
 pub enum TraceSeverity {
     Critical, // PCI data (credit cards, etc.)
     High,     // PII data (SSN, emails, etc.)
@@ -46,7 +217,45 @@ pub enum TraceSeverity {
     Low,      // Other identifiable data
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl TraceSeverity {
+    fn rank(&self) -> u8 {
+        match self {
+            TraceSeverity::Critical => 3,
+            TraceSeverity::High => 2,
+            TraceSeverity::Medium => 1,
+            TraceSeverity::Low => 0,
+        }
+    }
+}
+
+// Ordered by sensitivity: Critical > High > Medium > Low
+impl Ord for TraceSeverity {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.rank().cmp(&other.rank())
+    }
+}
+
+impl PartialOrd for TraceSeverity {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::str::FromStr for TraceSeverity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "critical" => Ok(TraceSeverity::Critical),
+            "high" => Ok(TraceSeverity::High),
+            "medium" => Ok(TraceSeverity::Medium),
+            "low" => Ok(TraceSeverity::Low),
+            _ => Err(format!("Unknown severity: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct CustomPattern {
     pub name: String,
     pub regex: String,
@@ -54,43 +263,136 @@ pub struct CustomPattern {
     pub category: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ProcessingConfig {
     pub batch_size: usize,
     pub preserve_structure: bool,
     pub trace_overlaps: bool,
     pub performance_mode: bool,
+    #[serde(default)]
+    pub json_aware: bool, // Apply rules to JSON string/number values, leaving keys and layout intact
+    #[serde(default)]
+    pub logfmt_aware: bool, // Apply rules to `key=value` values only, re-quoting as needed
+    #[serde(default)]
+    pub logfmt_sensitive_keys: Vec<String>, // If set, only these keys' values are scanned
+    #[serde(default)]
+    pub url_aware: bool, // Redact whole values of sensitive query parameters in http(s) URLs
+    #[serde(default = "default_url_sensitive_params")]
+    pub url_sensitive_params: Vec<String>, // Matched case-insensitively, after percent-decoding
+    #[serde(default)]
+    pub csv_aware: bool, // Apply rules per CSV field, keeping columns and quoting intact
+    #[serde(default = "default_csv_delimiter")]
+    pub csv_delimiter: char,
+    #[serde(default = "default_mask_char")]
+    pub mask_char: char, // Glyph used by the Phantom method
+    #[serde(default)]
+    pub grapheme_masking: bool, // Count Phantom's preserved head/tail in grapheme clusters, not chars
+    #[serde(default = "default_min_severity")]
+    pub min_severity: TraceSeverity, // Rules below this level are not applied
+    #[serde(default)]
+    pub syslog_aware: bool, // Leave RFC 3164/5424 headers untouched, phantom only the body
+    // Run rules over the whole input at once instead of line by line, so `(?s)` patterns
+    // can catch multi-line secrets such as PEM keys. Line-oriented options (max_line_bytes,
+    // ANSI stripping, syslog, logfmt) don't apply, nor do CSV/JSON when they parse first.
+    #[serde(default)]
+    pub document_mode: bool,
+    // Replace invalid input bytes with U+FFFD instead of failing. Best effort: the
+    // replaced bytes are gone from the output, and a secret split by one may go unmatched.
+    #[serde(default)]
+    pub lossy_utf8: bool,
+    pub max_matches_per_line: Option<usize>, // Per-rule replacement cap; guards against crafted lines
+    #[serde(default)]
+    pub min_confidence: f32, // Matches scoring below this (0.0-1.0) are left untouched
+    #[serde(default = "default_rule_order")]
+    pub rule_order: RuleOrder, // Which rule wins when matches overlap
+    #[serde(default = "default_encoding")]
+    pub input_encoding: String, // WHATWG label, e.g. "windows-1252" or "latin1"
+    #[serde(default = "default_encoding")]
+    pub output_encoding: String,
+    #[serde(default)]
+    pub deterministic_trace_ids: bool, // Derive trace IDs from rule, value and occurrence, not the clock
+    #[serde(default)]
+    pub reset_stats_per_file: bool, // Each phantom_file call starts with fresh stats and tokens
+    #[serde(default)]
+    pub keep_grand_total: bool, // With reset_stats_per_file, fold each file's stats into a running total
+    #[serde(default)]
+    pub shared_token_map: bool, // Sessions (e.g. TCP connections) share Mirror/Tokenize assignments
+    #[serde(default)]
+    pub strip_ansi: bool, // Remove ANSI escape sequences before matching, so colors can't split a value
+    #[serde(default)]
+    pub reinsert_ansi: bool, // With strip_ansi, put the sequences back instead of dropping them
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct OutputConfig {
     pub format: OutputFormat,
     pub include_trace_report: bool,
     pub log_phantom_events: bool,
     pub create_trace_map: bool,
+    #[serde(default)]
+    pub reversible_trace_map: bool, // Seal original -> phantom mappings into the trace map
+    pub sample_matches: Option<usize>, // Masked examples per rule in the trace report, for tuning
+    pub max_logged_events: Option<usize>, // Cap on events logged per result; past it a uniform sample is logged
+    pub report_path: Option<String>, // Trace report JSON written here after each file, whatever `format` is
+    // For output paths from untrusted sources: they must be relative, without `..`, and
+    // are resolved under this directory (the trace map lands next to them)
+    pub output_root: Option<String>,
+    #[serde(default)]
+    pub csv_omit_original: bool, // Drop the original_value column from CSV event output
+    #[serde(default)]
+    pub event_line_columns: bool, // Add line_number and char column to events, for UI highlighting
+    // Events written to JSON, JSON lines/array and CSV output carry `sha256:<hex>` of the
+    // original salted with `original_hash_salt`, or no original at all without a salt.
+    // On by default; `ProcessingResult` events in memory keep the originals either way.
+    #[serde(default = "default_hash_original_values")]
+    pub hash_original_values: bool,
+    pub original_hash_salt: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum OutputFormat {
     Text,
     Json,
     Csv,
     TraceReport,
+    SplunkHec, // One Splunk HTTP Event Collector event per line
+    Elk,       // One Elasticsearch document per line with phantom metadata
+    JsonLines, // One compact JSON object per line, written as each line is processed
+    JsonArray, // The JsonLines records as one JSON array, also written as each line is processed
+    Sarif,     // SARIF 2.1.0 log with one result per event, for security scanners
 }
 
 // New preprocessing configuration for log pipeline integration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PreprocessingConfig {
     pub mode: PreprocessingMode,
     pub realtime_processing: bool,
     pub tcp_server_port: Option<u16>,
+    #[serde(default)]
+    pub tcp_framing: TcpFraming,
+    // Largest `TcpFraming::LengthPrefixed` record accepted; a longer one ends the connection
+    #[serde(default = "default_max_frame_bytes")]
+    pub max_frame_bytes: usize,
     pub file_monitoring: bool,
     pub splunk_integration: SplunkConfig,
     pub elk_integration: ElkConfig,
     pub performance_tuning: PerformanceTuning,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How the TCP servers split a connection into records, and frame their replies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum TcpFraming {
+    #[default]
+    Line, // One record per newline-terminated line
+    // A 4-byte big-endian length, then that many bytes of UTF-8. A record may span lines
+    // (stack traces), and with `processing.document_mode` is phantomed as a whole.
+    LengthPrefixed,
+}
+
+/// Default for `preprocessing.max_frame_bytes`
+pub const DEFAULT_MAX_FRAME_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub enum PreprocessingMode {
     Standalone,      // Traditional file processing
     StreamProcessor, // Real-time stream processing (stdin/stdout)
@@ -98,7 +400,7 @@ pub enum PreprocessingMode {
     FileMonitor,     // Monitor log files for changes
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct SplunkConfig {
     pub enabled: bool,
     pub preserve_timestamp: bool,
@@ -109,7 +411,7 @@ pub struct SplunkConfig {
     pub host_field: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ElkConfig {
     pub enabled: bool,
     pub add_phantom_fields: bool,
@@ -118,7 +420,7 @@ pub struct ElkConfig {
     pub add_processing_metadata: bool,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PerformanceTuning {
     pub thread_pool_size: usize,
     pub buffer_size: usize,
@@ -126,15 +428,53 @@ pub struct PerformanceTuning {
     pub enable_batching: bool,
     pub async_processing: bool,
     pub memory_limit_mb: usize,
+    pub max_line_bytes: Option<usize>, // Longer lines never reach the regex engine
+    pub max_lines_per_second: Option<u32>, // Per TCP connection, so one agent can't starve the rest
+    #[serde(default = "default_rate_limit_policy")]
+    pub rate_limit_policy: RateLimitPolicy,
+    #[serde(default = "default_oversized_lines")]
+    pub oversized_lines: OversizedLinePolicy,
+}
+
+/// Order in which rules claim text. A match overlapping a span an earlier rule already
+/// claimed is dropped whole, so the first rule in this order wins a contested span.
+/// Declaration order breaks any remaining ties.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum RuleOrder {
+    Severity,    // Critical first, then by `priority`
+    Declaration, // As listed in the config
+    Priority,    // Highest `priority` first, regardless of severity
+}
+
+/// What a TCP connection over `performance_tuning.max_lines_per_second` gets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum RateLimitPolicy {
+    Block, // Stop reading until the budget refills; TCP backpressure slows the sender
+    Drop,  // Discard the excess lines unprocessed, with a warning
+}
+
+/// What happens to a line longer than `performance_tuning.max_line_bytes`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub enum OversizedLinePolicy {
+    PassThrough, // Emit unchanged (nothing in it is phantomed) and log a warning
+    Truncate,    // Phantom the first max_line_bytes, replace the rest with a marker
 }
 
 // Added missing monitoring configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MonitoringConfig {
     pub metrics_enabled: bool,
     pub metrics_interval: Duration,
     pub health_check_enabled: bool,
     pub audit_logging: bool,
+    #[serde(default = "default_audit_log_path")]
+    pub audit_log_path: String, // JSON lines, appended; never contains original values
+    #[serde(default)]
+    pub audit_include_hash: bool, // Add a salted SHA-256 of each original for correlation
+    pub audit_hash_salt: Option<String>, // Required with `audit_include_hash`
+    // Buffer events in memory and append them here every `flush_interval_ms`; results
+    // then keep only the events their output lists
+    pub event_buffer_path: Option<String>,
 }
 
 impl Default for PhantomTraceConfig {
@@ -145,18 +485,61 @@ impl Default for PhantomTraceConfig {
                 rules: default_trace_rules(),
                 custom_patterns: Vec::new(),
                 case_sensitive: false,
+                allowlist: Vec::new(),
+                allowlist_patterns: Vec::new(),
+                unicode_boundaries: default_unicode_boundaries(),
+                redact_keys: Vec::new(),
+                redact_method: default_redact_method(),
+                locales: Vec::new(),
+                rule_packs: Vec::new(),
+                default_methods: default_severity_methods(),
+                regex_size_limit: default_regex_size_limit(),
+                dfa_size_limit: default_dfa_size_limit(),
             },
             processing: ProcessingConfig {
                 batch_size: 1000,
                 preserve_structure: true,
                 trace_overlaps: true,
                 performance_mode: false,
+                json_aware: false,
+                logfmt_aware: false,
+                logfmt_sensitive_keys: Vec::new(),
+                url_aware: false,
+                url_sensitive_params: default_url_sensitive_params(),
+                csv_aware: false,
+                csv_delimiter: default_csv_delimiter(),
+                mask_char: default_mask_char(),
+                grapheme_masking: false,
+                min_severity: default_min_severity(),
+                syslog_aware: false,
+                document_mode: false,
+                lossy_utf8: false,
+                max_matches_per_line: None,
+                min_confidence: 0.0,
+                rule_order: default_rule_order(),
+                input_encoding: default_encoding(),
+                output_encoding: default_encoding(),
+                deterministic_trace_ids: false,
+                reset_stats_per_file: false,
+                keep_grand_total: false,
+                shared_token_map: false,
+                strip_ansi: false,
+                reinsert_ansi: false,
             },
             output: OutputConfig {
                 format: OutputFormat::Text,
                 include_trace_report: true,
                 log_phantom_events: false,
                 create_trace_map: false,
+                reversible_trace_map: false,
+                sample_matches: None,
+                max_logged_events: None,
+                report_path: None,
+                output_root: None,
+                csv_omit_original: false,
+                event_line_columns: false,
+                hash_original_values: true,
+                original_hash_salt: None,
             },
             preprocessing: PreprocessingConfig::default(),
             monitoring: MonitoringConfig::default(),
@@ -170,6 +553,8 @@ impl Default for PreprocessingConfig {
             mode: PreprocessingMode::Standalone,
             realtime_processing: false,
             tcp_server_port: Some(5140),
+            tcp_framing: TcpFraming::Line,
+            max_frame_bytes: DEFAULT_MAX_FRAME_BYTES,
             file_monitoring: false,
             splunk_integration: SplunkConfig {
                 enabled: false,
@@ -194,6 +579,10 @@ impl Default for PreprocessingConfig {
                 enable_batching: true,
                 async_processing: false,
                 memory_limit_mb: 512,
+                max_line_bytes: None,
+                max_lines_per_second: None,
+                rate_limit_policy: default_rate_limit_policy(),
+                oversized_lines: default_oversized_lines(),
             },
         }
     }
@@ -206,115 +595,787 @@ impl Default for MonitoringConfig {
             metrics_interval: Duration::from_secs(60),
             health_check_enabled: true,
             audit_logging: false,
+            audit_log_path: default_audit_log_path(),
+            audit_include_hash: false,
+            audit_hash_salt: None,
+            event_buffer_path: None,
         }
     }
 }
 
+fn default_csv_delimiter() -> char {
+    ','
+}
+
+fn default_audit_log_path() -> String {
+    "phantomtrace-audit.jsonl".to_string()
+}
+
+fn default_encoding() -> String {
+    "utf-8".to_string()
+}
+
+fn default_severity_methods() -> HashMap<TraceSeverity, ObfuscationMethod> {
+    HashMap::from([
+        (TraceSeverity::Critical, ObfuscationMethod::Vanish),
+        (TraceSeverity::High, ObfuscationMethod::Mirror),
+        (TraceSeverity::Medium, ObfuscationMethod::Phantom),
+        (TraceSeverity::Low, ObfuscationMethod::Phantom),
+    ])
+}
+
+fn default_redact_method() -> ObfuscationMethod {
+    ObfuscationMethod::Mask
+}
+
+fn default_url_sensitive_params() -> Vec<String> {
+    [
+        "token",
+        "access_token",
+        "api_key",
+        "apikey",
+        "key",
+        "sig",
+        "signature",
+        "password",
+    ]
+    .iter()
+    .map(|param| param.to_string())
+    .collect()
+}
+
+fn default_rule_order() -> RuleOrder {
+    RuleOrder::Severity
+}
+
+// Each `requires` cycle among `rules` as the names along it, first name repeated at the
+// end. A rule requiring itself is reported on its own, so it isn't followed here.
+fn requires_cycles(rules: &[TraceRule]) -> Vec<Vec<String>> {
+    fn visit<'a>(
+        name: &'a str,
+        by_name: &HashMap<&'a str, &'a TraceRule>,
+        path: &mut Vec<&'a str>,
+        done: &mut HashSet<&'a str>,
+        cycles: &mut Vec<Vec<String>>,
+    ) {
+        if done.contains(name) {
+            return;
+        }
+        if let Some(start) = path.iter().position(|on_path| *on_path == name) {
+            let mut cycle: Vec<String> = path[start..].iter().map(|n| n.to_string()).collect();
+            cycle.push(name.to_string());
+            cycles.push(cycle);
+            return;
+        }
+        let Some(rule) = by_name.get(name) else {
+            return;
+        };
+        path.push(name);
+        for required in rule.requires.iter().filter(|required| *required != name) {
+            visit(required, by_name, path, done, cycles);
+        }
+        path.pop();
+        done.insert(name);
+    }
+
+    let by_name: HashMap<&str, &TraceRule> = rules
+        .iter()
+        .map(|rule| (rule.name.as_str(), rule))
+        .collect();
+    let (mut done, mut cycles) = (HashSet::new(), Vec::new());
+    for rule in rules {
+        visit(
+            &rule.name,
+            &by_name,
+            &mut Vec::new(),
+            &mut done,
+            &mut cycles,
+        );
+    }
+    cycles
+}
+
+fn default_max_frame_bytes() -> usize {
+    DEFAULT_MAX_FRAME_BYTES
+}
+
+fn default_rate_limit_policy() -> RateLimitPolicy {
+    RateLimitPolicy::Block
+}
+
+fn default_oversized_lines() -> OversizedLinePolicy {
+    OversizedLinePolicy::Truncate
+}
+
+fn default_regex_size_limit() -> usize {
+    2 * (1 << 20)
+}
+
+fn default_dfa_size_limit() -> usize {
+    2 * (1 << 20)
+}
+
+fn default_unicode_boundaries() -> bool {
+    true
+}
+
+fn default_hash_original_values() -> bool {
+    true
+}
+
+fn default_rule_enabled() -> bool {
+    true
+}
+
+fn default_context_window() -> usize {
+    32
+}
+
+fn default_trim_chars() -> String {
+    ".,;:!?\"'()[]{}<>".to_string()
+}
+
+fn default_min_severity() -> TraceSeverity {
+    TraceSeverity::Low
+}
+
+pub(crate) fn default_mask_char() -> char {
+    '█'
+}
+
 fn default_trace_rules() -> Vec<TraceRule> {
     vec![
         // Credit Card Numbers (Critical PCI Data)
         TraceRule {
             name: "credit_card".to_string(),
             pattern: r"\b(?:\d{4}[-\s]?){3}\d{4}\b".to_string(),
-            method: ObfuscationMethod::Phantom,
+            method: Some(ObfuscationMethod::Card),
             preserve_chars: Some(4),
             replacement: None,
             severity: TraceSeverity::Critical,
+            validator: Some(MatchValidator::Luhn),
+            ..Default::default()
         },
         // Social Security Numbers (High PII)
         TraceRule {
             name: "ssn".to_string(),
             pattern: r"\b\d{3}-\d{2}-\d{4}\b".to_string(),
-            method: ObfuscationMethod::Mirror,
+            method: Some(ObfuscationMethod::Mirror),
             preserve_chars: None,
             replacement: None,
             severity: TraceSeverity::High,
+            ..Default::default()
         },
         // Email Addresses (High PII)
         TraceRule {
             name: "email".to_string(),
-            pattern: r"\b[A-Za-z0-9._%+-]+@[A-Za-z0-9.-]+\.[A-Z|a-z]{2,}\b".to_string(),
-            method: ObfuscationMethod::Phantom,
+            // Dot-separated domain labels ending in a letters-only TLD, so trailing
+            // punctuation like `user@example.com.` is left out of the match
+            pattern: r"\b[A-Za-z0-9._%+-]+@(?:[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?\.)+[A-Za-z]{2,}\b"
+                .to_string(),
+            method: Some(ObfuscationMethod::Phantom),
             preserve_chars: Some(3),
             replacement: None,
             severity: TraceSeverity::High,
+            ..Default::default()
         },
         // Phone Numbers (Medium PII)
         TraceRule {
             name: "phone".to_string(),
-            pattern: r"\b(?:\+1[-.\s]?)?(?:\([0-9]{3}\)|[0-9]{3})[-.\s]?[0-9]{3}[-.\s]?[0-9]{4}\b"
+            pattern: r"(?:\+1[-.\s]?(?:\([0-9]{3}\)|[0-9]{3})|\([0-9]{3}\)|\b[0-9]{3})[-.\s]?[0-9]{3}[-.\s]?[0-9]{4}\b"
                 .to_string(),
-            method: ObfuscationMethod::Phantom,
-            preserve_chars: Some(4),
+            method: Some(ObfuscationMethod::Phone),
+            preserve_chars: None,
             replacement: None,
             severity: TraceSeverity::Medium,
+            ..Default::default()
+        },
+        // Dates, e.g. dates of birth (Medium PII). One rule per format so each can be
+        // switched with `enabled`; month and day ranges keep version strings and
+        // arbitrary digit runs from matching.
+        TraceRule {
+            // Off by default: it would also match the ISO timestamps most log lines start with
+            enabled: false,
+            name: "date_iso".to_string(),
+            pattern: r"\b(?:19|20)\d{2}-(?:0[1-9]|1[0-2])-(?:0[1-9]|[12]\d|3[01])\b".to_string(),
+            method: Some(ObfuscationMethod::Mask),
+            preserve_chars: None,
+            replacement: Some("[DATE_PHANTOMED]".to_string()),
+            severity: TraceSeverity::Medium,
+            ..Default::default()
+        },
+        TraceRule {
+            // MM/DD/YYYY and DD/MM/YYYY
+            name: "date_slash".to_string(),
+            pattern: r"\b(?:0[1-9]|[12]\d|3[01])/(?:0[1-9]|[12]\d|3[01])/(?:19|20)\d{2}\b"
+                .to_string(),
+            method: Some(ObfuscationMethod::Mask),
+            preserve_chars: None,
+            replacement: Some("[DATE_PHANTOMED]".to_string()),
+            severity: TraceSeverity::Medium,
+            ..Default::default()
+        },
+        TraceRule {
+            // DD Mon YYYY, e.g. `07 Mar 1985` or `7 March 1985`
+            name: "date_month_name".to_string(),
+            pattern: r"(?i)\b(?:0?[1-9]|[12]\d|3[01]) (?:jan(?:uary)?|feb(?:ruary)?|mar(?:ch)?|apr(?:il)?|may|june?|july?|aug(?:ust)?|sep(?:t(?:ember)?)?|oct(?:ober)?|nov(?:ember)?|dec(?:ember)?) (?:19|20)\d{2}\b"
+                .to_string(),
+            method: Some(ObfuscationMethod::Mask),
+            preserve_chars: None,
+            replacement: Some("[DATE_PHANTOMED]".to_string()),
+            severity: TraceSeverity::Medium,
+            ..Default::default()
         },
         // IP Addresses (Medium Sensitive)
         TraceRule {
             name: "ip_address".to_string(),
             pattern: r"\b(?:[0-9]{1,3}\.){3}[0-9]{1,3}\b".to_string(),
-            method: ObfuscationMethod::Mask,
+            method: Some(ObfuscationMethod::Mask),
             preserve_chars: None,
             replacement: Some("XXX.XXX.XXX.XXX".to_string()),
             severity: TraceSeverity::Medium,
+            ..Default::default()
+        },
+        // MAC Addresses (Medium Sensitive). Separators must be consistent and every group
+        // exactly two hex digits, so ordinary IPv6 addresses (1-4 digit groups) don't match
+        TraceRule {
+            name: "mac_address".to_string(),
+            pattern:
+                r"\b(?:[0-9A-Fa-f]{2}:){5}[0-9A-Fa-f]{2}\b|\b(?:[0-9A-Fa-f]{2}-){5}[0-9A-Fa-f]{2}\b"
+                    .to_string(),
+            method: Some(ObfuscationMethod::Mask),
+            preserve_chars: None,
+            replacement: Some("[MAC_PHANTOMED]".to_string()),
+            severity: TraceSeverity::Medium,
+            ..Default::default()
+        },
+        // Bitcoin wallets, bech32 or legacy base58 (High quasi-identifier)
+        TraceRule {
+            name: "btc_address".to_string(),
+            pattern: r"\b(bc1[a-z0-9]{25,39}|[13][a-km-zA-HJ-NP-Z1-9]{25,34})\b".to_string(),
+            method: Some(ObfuscationMethod::Mirror),
+            preserve_chars: None,
+            replacement: None,
+            severity: TraceSeverity::High,
+            ..Default::default()
+        },
+        // Ethereum wallets. Exactly 40 hex digits, so color codes and short hex never match
+        TraceRule {
+            name: "eth_address".to_string(),
+            pattern: r"\b0x[a-fA-F0-9]{40}\b".to_string(),
+            method: Some(ObfuscationMethod::Mirror),
+            preserve_chars: None,
+            replacement: None,
+            severity: TraceSeverity::High,
+            ..Default::default()
+        },
+        // PEM private keys (Critical). Spans lines, so only found with `document_mode`;
+        // ranked first so a key's base64 body is never claimed piecemeal by other rules
+        TraceRule {
+            name: "private_key".to_string(),
+            pattern: r"-----BEGIN [A-Z ]+PRIVATE KEY-----[\s\S]+?-----END[^-]+-----".to_string(),
+            method: Some(ObfuscationMethod::Vanish),
+            preserve_chars: None,
+            replacement: None,
+            severity: TraceSeverity::Critical,
+            priority: 100,
+            ..Default::default()
         },
         // API Keys (Critical)
         TraceRule {
             name: "api_key".to_string(),
             pattern: r"\b[Aa][Pp][Ii][_-]?[Kk][Ee][Yy][:\s=]+[\w\-]{20,}\b".to_string(),
-            method: ObfuscationMethod::Mask,
+            method: Some(ObfuscationMethod::Mask),
             preserve_chars: None,
             replacement: Some("[API_KEY_PHANTOMED]".to_string()),
             severity: TraceSeverity::Critical,
+            ..Default::default()
         },
         // AWS Access Keys
         TraceRule {
             name: "aws_access_key".to_string(),
             pattern: r"\bAKIA[0-9A-Z]{16}\b".to_string(),
-            method: ObfuscationMethod::Mask,
+            method: Some(ObfuscationMethod::Mask),
             preserve_chars: None,
             replacement: Some("[AWS_KEY_PHANTOMED]".to_string()),
             severity: TraceSeverity::Critical,
+            ..Default::default()
         },
         // Generic Passwords
         TraceRule {
             name: "password".to_string(),
             pattern: r"\b[Pp][Aa][Ss][Ss][Ww][Oo][Rr][Dd][:\s=]+\S+".to_string(),
-            method: ObfuscationMethod::Mask,
+            method: Some(ObfuscationMethod::Mask),
             preserve_chars: None,
             replacement: Some("[PASSWORD_PHANTOMED]".to_string()),
             severity: TraceSeverity::Critical,
+            trim_match: true, // `\S+` would otherwise swallow trailing punctuation
+            ..Default::default()
         },
     ]
 }
 
+// Region-specific ID formats are ambiguous on their own (a DL number is just digits),
+// so every rule here requires a keyword shortly before the match
+const ID_CONTEXT_WINDOW: usize = 30;
+
+// (locale, rule name, pattern) for driver's licenses
+const DRIVERS_LICENSE_PATTERNS: [(&str, &str, &str); 4] = [
+    ("US-CA", "us_ca_drivers_license", r"\b[A-Z]\d{7}\b"),
+    (
+        "US-FL",
+        "us_fl_drivers_license",
+        r"\b[A-Z]\d{3}-?\d{3}-?\d{2}-?\d{3}-?\d\b",
+    ),
+    ("US-NY", "us_ny_drivers_license", r"\b\d{3} ?\d{3} ?\d{3}\b"),
+    ("US-TX", "us_tx_drivers_license", r"\b\d{8}\b"),
+];
+
+/// Government ID rules for one locale. `US` covers the passport and every state
+/// driver's license below; `US-CA`, `US-FL`, `US-NY` and `US-TX` add just that state's
+/// license. Matching is case-insensitive; unknown locales yield `None`.
+pub fn locale_trace_rules(locale: &str) -> Option<Vec<TraceRule>> {
+    let locale = locale.to_ascii_uppercase();
+    let drivers_license = |(_, name, pattern): &(&str, &str, &str)| TraceRule {
+        name: name.to_string(),
+        pattern: pattern.to_string(),
+        method: Some(ObfuscationMethod::Mask),
+        replacement: Some("[DL_PHANTOMED]".to_string()),
+        severity: TraceSeverity::High,
+        context_keywords: vec![
+            "driver".to_string(),
+            "license".to_string(),
+            "licence".to_string(),
+        ],
+        context_window: ID_CONTEXT_WINDOW,
+        ..Default::default()
+    };
+
+    if locale == "US" {
+        let mut rules = vec![TraceRule {
+            name: "us_passport".to_string(),
+            pattern: r"\b[A-Z]?\d{8,9}\b".to_string(),
+            method: Some(ObfuscationMethod::Mask),
+            replacement: Some("[PASSPORT_PHANTOMED]".to_string()),
+            severity: TraceSeverity::High,
+            context_keywords: vec!["passport".to_string()],
+            context_window: ID_CONTEXT_WINDOW,
+            ..Default::default()
+        }];
+        rules.extend(DRIVERS_LICENSE_PATTERNS.iter().map(drivers_license));
+        return Some(rules);
+    }
+    DRIVERS_LICENSE_PATTERNS
+        .iter()
+        .find(|(state, _, _)| *state == locale)
+        .map(|state| vec![drivers_license(state)])
+}
+
+/// Rules of a pack bundled with PhantomTrace: `pci`, `hipaa` or `gdpr`
+/// (case-insensitive). Unknown names yield `None`.
+pub fn bundled_rule_pack(name: &str) -> Option<Vec<TraceRule>> {
+    let rule = |name: &str, pattern: &str, replacement: &str, keywords: &[&str]| TraceRule {
+        name: name.to_string(),
+        pattern: pattern.to_string(),
+        method: Some(ObfuscationMethod::Mask),
+        replacement: Some(replacement.to_string()),
+        severity: TraceSeverity::High,
+        context_keywords: keywords.iter().map(|keyword| keyword.to_string()).collect(),
+        context_window: ID_CONTEXT_WINDOW,
+        ..Default::default()
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        "pci" => Some(vec![
+            TraceRule {
+                severity: TraceSeverity::Critical,
+                ..rule(
+                    "pci_card_security_code",
+                    r"\b\d{3,4}\b",
+                    "[CSC_PHANTOMED]",
+                    &["cvv", "cvc", "cvv2", "security code"],
+                )
+            },
+            rule(
+                "pci_card_expiry",
+                r"\b(?:0[1-9]|1[0-2])/(?:\d{2}|\d{4})\b",
+                "[EXPIRY_PHANTOMED]",
+                &["exp", "expiry", "expires", "expiration"],
+            ),
+        ]),
+        "hipaa" => Some(vec![
+            rule(
+                "hipaa_medical_record_number",
+                r"\b[A-Z]{0,3}\d{6,10}\b",
+                "[MRN_PHANTOMED]",
+                &["mrn", "medical record", "patient id"],
+            ),
+            rule(
+                "hipaa_npi",
+                r"\b\d{10}\b",
+                "[NPI_PHANTOMED]",
+                &["npi", "provider"],
+            ),
+        ]),
+        "gdpr" => Some(vec![
+            TraceRule {
+                context_keywords: Vec::new(),
+                ..rule(
+                    "gdpr_iban",
+                    r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){2,7}(?: ?[A-Z0-9]{1,4})?\b",
+                    "[IBAN_PHANTOMED]",
+                    &[],
+                )
+            },
+            TraceRule {
+                context_keywords: Vec::new(),
+                ..rule(
+                    "gdpr_uk_national_insurance",
+                    r"\b[A-CEGHJ-PR-TW-Z]{2} ?\d{2} ?\d{2} ?\d{2} ?[A-D]\b",
+                    "[NINO_PHANTOMED]",
+                    &[],
+                )
+            },
+        ]),
+        _ => None,
+    }
+}
+
+/// Rules of one `tracing.rule_packs` entry: a bundled pack name, otherwise the path of
+/// a JSON rule pack file
+pub fn load_rule_pack(entry: &str) -> Result<Vec<TraceRule>, PhantomError> {
+    if let Some(rules) = bundled_rule_pack(entry) {
+        return Ok(rules);
+    }
+    let content = std::fs::read_to_string(entry)
+        .map_err(|e| PhantomError::Config(format!("Cannot read rule pack '{}': {}", entry, e)))?;
+    let pack: RulePack = serde_json::from_str(&content)?;
+    Ok(pack.rules)
+}
+
+impl TracingConfig {
+    pub fn regex_limits(&self) -> RegexLimits {
+        RegexLimits {
+            size_limit: self.regex_size_limit,
+            dfa_size_limit: self.dfa_size_limit,
+        }
+    }
+}
+
+/// Memory caps applied when compiling rule patterns
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegexLimits {
+    pub size_limit: usize,
+    pub dfa_size_limit: usize,
+}
+
+impl Default for RegexLimits {
+    fn default() -> Self {
+        Self {
+            size_limit: default_regex_size_limit(),
+            dfa_size_limit: default_dfa_size_limit(),
+        }
+    }
+}
+
+impl RegexLimits {
+    /// Compile `pattern` for `rule` within these limits
+    pub fn build(&self, rule: &str, pattern: &str) -> Result<regex::Regex, PhantomError> {
+        regex::RegexBuilder::new(pattern)
+            .size_limit(self.size_limit)
+            .dfa_size_limit(self.dfa_size_limit)
+            .build()
+            .map_err(|source| match source {
+                regex::Error::CompiledTooBig(limit) => PhantomError::Config(format!(
+                    "Rule '{}' pattern compiles to more than the {} bytes allowed by \
+                     tracing.regex_size_limit; simplify it (e.g. smaller repetition counts) \
+                     or raise the limit",
+                    rule, limit
+                )),
+                source => PhantomError::InvalidRegex {
+                    rule: rule.to_string(),
+                    source,
+                },
+            })
+    }
+}
+
 impl PhantomTraceConfig {
-    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+    /// Append the rules of `tracing.rule_packs` to `tracing.rules`. On a name clash the
+    /// configured rule wins, then the pack listed first; locale rules come last of all.
+    /// Merging again is a no-op.
+    pub fn merge_rule_packs(&mut self) -> Result<(), PhantomError> {
+        for entry in &self.tracing.rule_packs {
+            for rule in load_rule_pack(entry)? {
+                if !self
+                    .tracing
+                    .rules
+                    .iter()
+                    .any(|existing| existing.name == rule.name)
+                {
+                    self.tracing.rules.push(rule);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Configured rules followed by the rules of `tracing.locales`. A configured rule
+    /// with the same name as a locale rule replaces it. Every returned rule has a
+    /// `method`: rules without one get `tracing.default_methods` for their severity,
+    /// or `Phantom` if that severity has no default.
+    pub fn effective_rules(&self) -> Vec<TraceRule> {
+        let mut rules = self.tracing.rules.clone();
+        for locale in &self.tracing.locales {
+            for rule in locale_trace_rules(locale).unwrap_or_default() {
+                if !rules.iter().any(|existing| existing.name == rule.name) {
+                    rules.push(rule);
+                }
+            }
+        }
+        for rule in &mut rules {
+            if rule.method.is_none() {
+                rule.method = Some(
+                    self.tracing
+                        .default_methods
+                        .get(&rule.severity)
+                        .cloned()
+                        .unwrap_or(ObfuscationMethod::Phantom),
+                );
+            }
+        }
+        rules
+    }
+
+    /// Turn off the rule called `name`, a configured rule or one of `tracing.locales`.
+    /// A locale rule is disabled by adding a disabled copy to `tracing.rules`.
+    pub fn disable_rule(&mut self, name: &str) -> Result<(), PhantomError> {
+        if let Some(rule) = self.tracing.rules.iter_mut().find(|rule| rule.name == name) {
+            rule.enabled = false;
+            return Ok(());
+        }
+        let locale_rule = self
+            .tracing
+            .locales
+            .iter()
+            .flat_map(|locale| locale_trace_rules(locale).unwrap_or_default())
+            .find(|rule| rule.name == name)
+            .ok_or_else(|| {
+                PhantomError::Config(format!("Cannot disable unknown rule: {}", name))
+            })?;
+        self.tracing.rules.push(TraceRule {
+            enabled: false,
+            ..locale_rule
+        });
+        Ok(())
+    }
+
+    pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self, PhantomError> {
         let content = std::fs::read_to_string(path)?;
-        let config: PhantomTraceConfig = serde_json::from_str(&content)?;
+        let mut config: PhantomTraceConfig = serde_json::from_str(&content)?;
+        config.merge_rule_packs()?;
+        config.validate()?;
         Ok(config)
     }
 
-    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn std::error::Error>> {
+    /// Check that every rule has a unique, non-empty name, a pattern that compiles with
+    /// the groups its `obfuscate_groups` name, and `requires` naming other configured or
+    /// locale rules without a cycle, disabled rules included, and that allowlist and
+    /// redact-key patterns compile
+    pub fn validate(&self) -> Result<(), PhantomError> {
+        match self.validation_errors().into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Every problem `validate` checks for, in the order it would report them,
+    /// rather than only the first
+    pub fn validation_errors(&self) -> Vec<PhantomError> {
+        let mut errors = Vec::new();
+        let mut names = HashSet::new();
+        for (index, rule) in self.tracing.rules.iter().enumerate() {
+            if rule.name.trim().is_empty() {
+                errors.push(PhantomError::Config(format!(
+                    "Rule #{} has an empty name",
+                    index + 1
+                )));
+            } else if !names.insert(rule.name.as_str()) {
+                errors.push(PhantomError::Config(format!(
+                    "Duplicate rule name '{}'",
+                    rule.name
+                )));
+            }
+            match self.tracing.regex_limits().build(&rule.name, &rule.pattern) {
+                Ok(regex) => {
+                    if let Some(missing) = rule
+                        .obfuscate_groups
+                        .iter()
+                        .find(|group| !regex.capture_names().flatten().any(|name| name == *group))
+                    {
+                        errors.push(PhantomError::Config(format!(
+                            "Rule '{}' has no capture group named '{}'",
+                            rule.name, missing
+                        )));
+                    }
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+        // Locale rules can be required too
+        let effective = self.effective_rules();
+        let known: HashSet<&str> = effective.iter().map(|rule| rule.name.as_str()).collect();
+        for rule in &effective {
+            for required in &rule.requires {
+                if *required == rule.name {
+                    errors.push(PhantomError::Config(format!(
+                        "Rule '{}' requires itself",
+                        rule.name
+                    )));
+                } else if !known.contains(required.as_str()) {
+                    errors.push(PhantomError::Config(format!(
+                        "Rule '{}' requires unknown rule '{}'",
+                        rule.name, required
+                    )));
+                }
+            }
+        }
+        // No rule in a cycle could ever apply
+        for cycle in requires_cycles(&effective) {
+            errors.push(PhantomError::Config(format!(
+                "Rules {} require each other in a cycle",
+                cycle
+                    .iter()
+                    .map(|name| format!("'{}'", name))
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            )));
+        }
+        if !self.processing.csv_delimiter.is_ascii() {
+            errors.push(PhantomError::Config(
+                "CSV delimiter must be an ASCII character".into(),
+            ));
+        }
+        for locale in &self.tracing.locales {
+            if locale_trace_rules(locale).is_none() {
+                errors.push(PhantomError::Config(format!("Unknown locale '{}'", locale)));
+            }
+        }
+        for pattern in &self.tracing.allowlist_patterns {
+            if let Err(source) = regex::Regex::new(pattern) {
+                errors.push(PhantomError::InvalidRegex {
+                    rule: "allowlist".to_string(),
+                    source,
+                });
+            }
+        }
+        for key in &self.tracing.redact_keys {
+            if let Err(source) = regex::Regex::new(key) {
+                errors.push(PhantomError::InvalidRegex {
+                    rule: "redact_keys".to_string(),
+                    source,
+                });
+            }
+        }
+        errors
+    }
+
+    /// Load `path` like `load_from_file`, but report every problem instead of the first.
+    /// A file that can't be read or doesn't fit the config schema is a single problem.
+    pub fn validate_file<P: AsRef<Path>>(path: P) -> Result<Self, Vec<PhantomError>> {
+        let loaded = std::fs::read_to_string(path)
+            .map_err(PhantomError::from)
+            .and_then(|content| Ok(serde_json::from_str::<PhantomTraceConfig>(&content)?))
+            .and_then(|mut config| config.merge_rule_packs().map(|_| config));
+        let config = loaded.map_err(|error| vec![error])?;
+        let errors = config.validation_errors();
+        if errors.is_empty() {
+            Ok(config)
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), PhantomError> {
         let content = serde_json::to_string_pretty(self)?;
         std::fs::write(path, content)?;
         Ok(())
     }
 
-    pub fn get_rules_by_severity(&self, _severity: TraceSeverity) -> Vec<&TraceRule> {
+    pub fn get_rules_by_severity(&self, severity: TraceSeverity) -> Vec<&TraceRule> {
         self.tracing
             .rules
             .iter()
-            .filter(|rule| matches!(&rule.severity, _severity))
+            .filter(|rule| rule.severity == severity)
             .collect()
     }
 
+    /// Every configured rule as `--list-rules` shows it. `enabled` is the effective
+    /// state: the rule's own switch combined with `processing.min_severity`.
+    pub fn rule_summaries(&self) -> Vec<RuleSummary> {
+        self.effective_rules()
+            .iter()
+            .map(|rule| RuleSummary {
+                name: rule.name.clone(),
+                severity: rule.severity.clone(),
+                method: rule.method.clone().unwrap_or(ObfuscationMethod::Phantom),
+                enabled: rule.enabled && rule.severity >= self.processing.min_severity,
+            })
+            .collect()
+    }
+
+    pub fn rules_table(&self) -> String {
+        let summaries = self.rule_summaries();
+        let name_width = summaries
+            .iter()
+            .map(|summary| summary.name.len())
+            .chain(std::iter::once("NAME".len()))
+            .max()
+            .unwrap_or(0);
+
+        let mut table = format!(
+            "{:<name_width$}  {:<8}  {:<8}  ENABLED\n",
+            "NAME", "SEVERITY", "METHOD"
+        );
+        for summary in &summaries {
+            table.push_str(&format!(
+                "{:<name_width$}  {:<8}  {:<8}  {}\n",
+                summary.name,
+                format!("{:?}", summary.severity),
+                format!("{:?}", summary.method),
+                if summary.enabled { "yes" } else { "no" }
+            ));
+        }
+        table
+    }
+
+    /// JSON Schema of the configuration file, derived from these serde structs
+    pub fn json_schema() -> serde_json::Value {
+        schemars::schema_for!(PhantomTraceConfig).to_value()
+    }
+
+    /// The preset called `name`, one of `PRESET_NAMES`
+    pub fn from_preset(name: &str) -> Result<Self, PhantomError> {
+        match name {
+            "default" => Ok(Self::default()),
+            "splunk" => Ok(Self::splunk_preset()),
+            "elk" => Ok(Self::elk_preset()),
+            "high-performance" => Ok(Self::high_performance_preset()),
+            "strict-pci" => Ok(Self::strict_pci_preset()),
+            _ => Err(PhantomError::Config(format!(
+                "Unknown preset '{}' (expected one of: {})",
+                name,
+                PRESET_NAMES.join(", ")
+            ))),
+        }
+    }
+
     // Configuration presets for different use cases
     pub fn splunk_preset() -> Self {
         let mut config = Self::default();
         config.preprocessing.mode = PreprocessingMode::StreamProcessor;
         config.preprocessing.splunk_integration.enabled = true;
-        config.output.format = OutputFormat::Json;
+        config.output.format = OutputFormat::SplunkHec;
         config.output.include_trace_report = false;
         config.preprocessing.realtime_processing = true;
         config
@@ -324,7 +1385,7 @@ impl PhantomTraceConfig {
         let mut config = Self::default();
         config.preprocessing.mode = PreprocessingMode::StreamProcessor;
         config.preprocessing.elk_integration.enabled = true;
-        config.output.format = OutputFormat::Json;
+        config.output.format = OutputFormat::Elk;
         config.output.include_trace_report = false;
         config.preprocessing.realtime_processing = true;
         config
@@ -354,18 +1415,21 @@ impl PhantomTraceConfig {
             TraceRule {
                 name: "cvv".to_string(),
                 pattern: r"\b[Cc][Vv][Vv]?[:\s=]*\d{3,4}\b".to_string(),
-                method: ObfuscationMethod::Vanish,
+                method: Some(ObfuscationMethod::Vanish),
                 preserve_chars: None,
                 replacement: None,
                 severity: TraceSeverity::Critical,
+                ..Default::default()
             },
             TraceRule {
                 name: "bank_account".to_string(),
                 pattern: r"\b\d{8,17}\b".to_string(),
-                method: ObfuscationMethod::Mirror,
+                method: Some(ObfuscationMethod::Mirror),
                 preserve_chars: None,
                 replacement: None,
                 severity: TraceSeverity::Critical,
+                validator: Some(MatchValidator::Entropy { min_unique: 4 }),
+                ..Default::default()
             },
         ]);
         config
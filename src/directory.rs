@@ -0,0 +1,108 @@
+use crate::error::PhantomError;
+use crate::processor::{PhantomTraceProcessor, ProcessingResult};
+use std::path::{Path, PathBuf};
+
+/// Which files under an input directory are processed
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryOptions {
+    /// Descend into subdirectories
+    pub recursive: bool,
+    /// `*`/`?` pattern matched against each file name, e.g. `*.log`
+    pub glob: Option<String>,
+}
+
+/// Phantom every selected file under `input_dir` into `output_dir`, keeping each
+/// file's path relative to `input_dir`. `on_file` is called after each file with
+/// its input path, output path and result. Returns the number of files processed.
+pub fn phantom_directory(
+    processor: &mut PhantomTraceProcessor,
+    input_dir: &Path,
+    output_dir: &Path,
+    options: &DirectoryOptions,
+    mut on_file: impl FnMut(&Path, &Path, &ProcessingResult, &PhantomTraceProcessor),
+) -> Result<usize, PhantomError> {
+    let files = list_files(input_dir, output_dir, options)?;
+    for relative in &files {
+        let input_path = input_dir.join(relative);
+        let output_path = output_dir.join(relative);
+        if let Some(parent) = output_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let result = processor.phantom_file(
+            &input_path.to_string_lossy(),
+            &output_path.to_string_lossy(),
+        )?;
+        on_file(&input_path, &output_path, &result, processor);
+    }
+    Ok(files.len())
+}
+
+/// Paths relative to `input_dir`, sorted so runs are reproducible.
+/// `output_dir` is skipped when it lives inside `input_dir`.
+pub fn list_files(
+    input_dir: &Path,
+    output_dir: &Path,
+    options: &DirectoryOptions,
+) -> Result<Vec<PathBuf>, PhantomError> {
+    if !input_dir.is_dir() {
+        return Err(PhantomError::Config(format!(
+            "Input directory '{}' does not exist",
+            input_dir.display()
+        )));
+    }
+    let skip = output_dir.canonicalize().ok();
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in std::fs::read_dir(input_dir.join(&relative))? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                if options.recursive && path.canonicalize().ok() != skip {
+                    pending.push(relative.join(entry.file_name()));
+                }
+            } else if file_type.is_file() {
+                let name = entry.file_name();
+                let selected = options
+                    .glob
+                    .as_deref()
+                    .is_none_or(|glob| glob_matches(glob, &name.to_string_lossy()));
+                if selected {
+                    files.push(relative.join(name));
+                }
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+// Shell-style match: `*` is any run of characters, `?` exactly one
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
@@ -0,0 +1,55 @@
+use crate::error::PhantomError;
+use encoding_rs::Encoding;
+
+fn lookup(label: &str) -> Result<&'static Encoding, PhantomError> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| PhantomError::Config(format!("Unknown encoding '{}'", label)))
+}
+
+/// Decode file contents, rejecting bytes that are invalid in the given encoding
+pub(crate) fn decode(bytes: &[u8], label: &str) -> Result<String, PhantomError> {
+    let encoding = lookup(label)?;
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|text| text.into_owned())
+        .ok_or_else(|| PhantomError::Encoding(format!("Input is not valid {}", encoding.name())))
+}
+
+/// Decode file contents, replacing invalid byte sequences with U+FFFD
+pub(crate) fn decode_lossy(bytes: &[u8], label: &str) -> Result<String, PhantomError> {
+    let (text, _) = lookup(label)?.decode_without_bom_handling(bytes);
+    Ok(text.into_owned())
+}
+
+/// One line of a UTF-8 stream, replacing invalid sequences when `lossy`
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn utf8_line(bytes: &[u8], lossy: bool) -> std::io::Result<std::borrow::Cow<'_, str>> {
+    if lossy {
+        Ok(String::from_utf8_lossy(bytes))
+    } else {
+        std::str::from_utf8(bytes)
+            .map(std::borrow::Cow::Borrowed)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Encode output text, failing rather than substituting characters the encoding can't hold
+pub(crate) fn encode(text: &str, label: &str) -> Result<Vec<u8>, PhantomError> {
+    let encoding = lookup(label)?;
+    // encoding_rs only decodes UTF-16; encoding to it silently produces UTF-8
+    if encoding.output_encoding() != encoding {
+        return Err(PhantomError::Encoding(format!(
+            "Output cannot be written as {}",
+            encoding.name()
+        )));
+    }
+
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(PhantomError::Encoding(format!(
+            "Output contains characters {} cannot represent (is processing.mask_char one of them?)",
+            encoding.name()
+        )));
+    }
+    Ok(bytes.into_owned())
+}
@@ -0,0 +1,26 @@
+use thiserror::Error;
+
+/// Errors returned by the PhantomTrace public API
+#[derive(Debug, Error)]
+pub enum PhantomError {
+    #[error("Invalid regex pattern in rule '{rule}': {source}")]
+    InvalidRegex { rule: String, source: regex::Error },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Configuration error: {0}")]
+    Config(String),
+
+    #[error("Serialization error: {0}")]
+    Serde(#[from] serde_json::Error),
+
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("Trace map error: {0}")]
+    TraceMap(String),
+
+    #[error("Encoding error: {0}")]
+    Encoding(String),
+}
@@ -0,0 +1,129 @@
+use crate::audit::AuditLog;
+use crate::error::PhantomError;
+use crate::tracer::PhantomEvent;
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+/// Events held in memory until a background thread appends them to disk every
+/// `flush_interval`, so a long-running server's memory stays bounded by what one
+/// interval produces. With a buffer configured, processing results keep only the
+/// events their output lists; the buffer holds the rest. Records use the audit log
+/// format and never contain original values. Clones share one buffer; the last one
+/// dropped flushes what's left.
+#[derive(Debug, Clone)]
+pub struct EventBuffer {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    events: Mutex<Vec<PhantomEvent>>,
+    sink: AuditLog,
+    flushed: AtomicU64,
+    failed_flush: Mutex<Option<PhantomError>>, // From the flush thread, until reported
+}
+
+impl EventBuffer {
+    /// Append flushed events to `path` and start the flush thread.
+    /// The thread exits at its next tick once every clone is dropped.
+    pub fn open(
+        path: &str,
+        hash_salt: Option<String>,
+        flush_interval: Duration,
+    ) -> Result<Self, PhantomError> {
+        if cfg!(target_arch = "wasm32") {
+            return Err(PhantomError::Config(
+                "event_buffer_path needs a background thread, which wasm32 lacks".into(),
+            ));
+        }
+        let inner = Arc::new(Inner {
+            events: Mutex::new(Vec::new()),
+            sink: AuditLog::open(path, hash_salt)?,
+            flushed: AtomicU64::new(0),
+            failed_flush: Mutex::new(None),
+        });
+        let weak = Arc::downgrade(&inner);
+        // A zero interval would spin
+        let interval = flush_interval.max(Duration::from_millis(1));
+        thread::spawn(move || flush_periodically(weak, interval));
+        Ok(Self { inner })
+    }
+
+    /// Buffer `events`. An error reports a failed background flush since the last
+    /// `push` or `flush`; the events it couldn't write stay buffered for the next one.
+    pub fn push(&self, events: &[PhantomEvent]) -> Result<(), PhantomError> {
+        if !events.is_empty() {
+            self.inner.lock_events().extend_from_slice(events);
+        }
+        self.inner.take_failed_flush()
+    }
+
+    /// Write buffered events to disk now, returning how many were written
+    pub fn flush(&self) -> Result<usize, PhantomError> {
+        self.inner.take_failed_flush()?;
+        self.inner.flush()
+    }
+
+    /// Events waiting for the next flush
+    pub fn buffered(&self) -> usize {
+        self.inner.lock_events().len()
+    }
+
+    /// Events written to disk so far
+    pub fn flushed(&self) -> u64 {
+        self.inner.flushed.load(Ordering::Relaxed)
+    }
+}
+
+impl Inner {
+    fn lock_events(&self) -> std::sync::MutexGuard<'_, Vec<PhantomEvent>> {
+        self.events.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn take_failed_flush(&self) -> Result<(), PhantomError> {
+        let failed = self
+            .failed_flush
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take();
+        failed.map_or(Ok(()), Err)
+    }
+
+    fn flush(&self) -> Result<usize, PhantomError> {
+        // Swap the buffer out so processing never waits on the disk write
+        let events = mem::take(&mut *self.lock_events());
+        if let Err(e) = self.sink.record(&events) {
+            // Keep them for the next attempt, ahead of anything pushed meanwhile
+            let mut buffered = self.lock_events();
+            let newer = mem::replace(&mut *buffered, events);
+            buffered.extend(newer);
+            return Err(e);
+        }
+        self.flushed
+            .fetch_add(events.len() as u64, Ordering::Relaxed);
+        Ok(events.len())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        // Nobody is left to report to; call `flush` before dropping to see errors
+        let _ = self.flush();
+    }
+}
+
+fn flush_periodically(inner: Weak<Inner>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(inner) = inner.upgrade() else {
+            return;
+        };
+        if let Err(e) = inner.flush() {
+            // Reported by the next `push` or `flush`; a later failure replaces it
+            *inner.failed_flush.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+        }
+    }
+}
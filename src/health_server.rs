@@ -0,0 +1,96 @@
+use crate::error::PhantomError;
+use crate::metrics;
+use crate::processor::ProcessingStats;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimal HTTP endpoint: `GET /health`, plus `GET /metrics` in Prometheus text
+/// format when `metrics_enabled`. Metrics are read from `stats` at request time; give
+/// the processors doing the work the same handle (`set_stats_sink`) so they are live.
+/// Each request gets its own thread. Runs until `shutdown` is set.
+pub fn serve_listener(
+    listener: TcpListener,
+    stats: Arc<Mutex<ProcessingStats>>,
+    metrics_enabled: bool,
+    shutdown: Arc<AtomicBool>,
+) -> Result<(), PhantomError> {
+    listener.set_nonblocking(true)?;
+
+    while !shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                let stats = stats.clone();
+                // A slow client must not hold up the next probe
+                thread::spawn(move || {
+                    if let Err(e) = handle_request(stream, &stats, metrics_enabled) {
+                        eprintln!("Health request error: {}", e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => eprintln!("Connection failed: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_request(
+    stream: TcpStream,
+    stats: &Mutex<ProcessingStats>,
+    metrics_enabled: bool,
+) -> Result<(), PhantomError> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Drain headers; nothing in them matters here
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/health") => ("200 OK", "text/plain", "OK\n".to_string()),
+        ("GET", "/metrics") => {
+            if metrics_enabled {
+                // A poisoned lock still holds valid counters
+                let stats = stats.lock().unwrap_or_else(|e| e.into_inner());
+                (
+                    "200 OK",
+                    "text/plain; version=0.0.4",
+                    metrics::render_prometheus(&stats),
+                )
+            } else {
+                (
+                    "404 Not Found",
+                    "text/plain",
+                    "Metrics disabled\n".to_string(),
+                )
+            }
+        }
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use crate::clock;
+use crate::config::TraceSeverity;
+use crate::config::{ElkConfig, SplunkConfig};
+use crate::processor::ProcessingResult;
+use crate::tracer::PhantomEvent;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Build a Splunk HTTP Event Collector event for one processed line
+pub fn splunk_hec_event(
+    splunk: &SplunkConfig,
+    source: &str,
+    original_line: &str,
+    result: &ProcessingResult,
+) -> Value {
+    let timestamp = if splunk.preserve_timestamp {
+        leading_timestamp(original_line).unwrap_or_else(clock::now)
+    } else {
+        clock::now()
+    };
+
+    let mut event = Map::new();
+    event.insert("time".to_string(), json!(epoch_seconds(timestamp)));
+    if let Some(host) = &splunk.host_field {
+        event.insert("host".to_string(), json!(host));
+    }
+    event.insert(
+        "source".to_string(),
+        json!(if splunk.preserve_source {
+            source
+        } else {
+            "phantomtrace"
+        }),
+    );
+    event.insert("sourcetype".to_string(), json!(splunk.phantom_sourcetype));
+    if let Some(index) = &splunk.index {
+        event.insert("index".to_string(), json!(index));
+    }
+    event.insert("event".to_string(), json!(result.phantomed_text));
+
+    // HEC indexed fields, so searches can filter on phantom activity
+    if splunk.add_phantom_metadata {
+        // Counted over every event, including any `phantom_events` doesn't keep
+        let rules: Vec<&str> = result.events_by_rule.keys().map(String::as_str).collect();
+        event.insert(
+            "fields".to_string(),
+            json!({
+                "phantom_events": result.total_events,
+                "phantom_rules": rules,
+            }),
+        );
+    }
+
+    Value::Object(event)
+}
+
+/// Build an Elasticsearch document for one processed line
+pub fn elk_document(elk: &ElkConfig, original_line: &str, result: &ProcessingResult) -> Value {
+    let processed_at = clock::now();
+    let timestamp = if elk.preserve_original_timestamp {
+        leading_timestamp(original_line).unwrap_or(processed_at)
+    } else {
+        processed_at
+    };
+
+    let mut document = Map::new();
+    document.insert(
+        "@timestamp".to_string(),
+        json!(humantime::format_rfc3339_millis(timestamp).to_string()),
+    );
+    document.insert("message".to_string(), json!(result.phantomed_text));
+    // Routing hint for Logstash; @metadata is never indexed
+    document.insert(
+        "@metadata".to_string(),
+        json!({ "index_pattern": elk.phantom_index_pattern }),
+    );
+
+    let mut phantom = Map::new();
+    if elk.add_phantom_fields {
+        // Counted over every event, including any `phantom_events` doesn't keep
+        let rules_triggered: Vec<&str> = result.events_by_rule.keys().map(String::as_str).collect();
+
+        let mut severity_counts = BTreeMap::new();
+        for (severity, events) in result.events_by_rule.values() {
+            *severity_counts
+                .entry(format!("{:?}", severity))
+                .or_insert(0u64) += events;
+        }
+
+        phantom.insert("rules_triggered".to_string(), json!(rules_triggered));
+        phantom.insert("severity_counts".to_string(), json!(severity_counts));
+        phantom.insert("event_count".to_string(), json!(result.total_events));
+    }
+    if elk.add_processing_metadata {
+        phantom.insert(
+            "processed_at".to_string(),
+            json!(humantime::format_rfc3339_millis(processed_at).to_string()),
+        );
+        phantom.insert(
+            "processing_time_us".to_string(),
+            json!(result.processing_time.as_micros() as u64),
+        );
+    }
+    if !phantom.is_empty() {
+        document.insert("phantom".to_string(), Value::Object(phantom));
+    }
+
+    Value::Object(document)
+}
+
+/// Build the JSON-lines record for one processed line. The original text is never
+/// included; `line` is its 1-based line number, and `events` are listed if given.
+pub fn json_line_record(
+    line_number: u64,
+    result: &ProcessingResult,
+    events: Option<&[PhantomEvent]>,
+) -> Value {
+    let mut record = Map::new();
+    record.insert("line".to_string(), json!(line_number));
+    record.insert("phantomed".to_string(), json!(result.phantomed_text));
+    if let Some(events) = events {
+        record.insert("events".to_string(), json!(events));
+    }
+    Value::Object(record)
+}
+
+const SARIF_SCHEMA: &str =
+    "https://docs.oasis-open.org/sarif/sarif/v2.1.0/errata01/os/schemas/sarif-schema-2.1.0.json";
+
+/// Build a SARIF 2.1.0 log for `artifact`, given each processed line in order. Results
+/// carry 1-based line and column (in characters) regions and only phantom values as
+/// snippets, never original text.
+pub fn sarif_log(artifact: &str, lines: &[(&str, &ProcessingResult)]) -> Value {
+    let mut rules: BTreeMap<&str, &TraceSeverity> = BTreeMap::new();
+    let mut results = Vec::new();
+
+    for (index, (line, result)) in lines.iter().enumerate() {
+        for event in &result.phantom_events {
+            rules.insert(&event.rule_name, &event.severity);
+            let column = |byte: usize| line.get(..byte).map_or(0, |s| s.chars().count()) + 1;
+            results.push(json!({
+                "ruleId": event.rule_name,
+                "level": sarif_level(&event.severity),
+                "message": {
+                    "text": format!("{} ({:?} severity) value found", event.rule_name, event.severity)
+                },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": artifact },
+                        "region": {
+                            "startLine": index + 1,
+                            "startColumn": column(event.position.0),
+                            "endColumn": column(event.position.1),
+                            "snippet": { "text": event.phantom_value }
+                        },
+                        "contextRegion": {
+                            "startLine": index + 1,
+                            "snippet": { "text": result.phantomed_text }
+                        }
+                    }
+                }],
+                "properties": { "traceId": event.trace_id }
+            }));
+        }
+    }
+
+    let rules: Vec<Value> = rules
+        .into_iter()
+        .map(|(rule_name, severity)| {
+            json!({
+                "id": rule_name,
+                "defaultConfiguration": { "level": sarif_level(severity) },
+                "properties": { "severity": format!("{:?}", severity) }
+            })
+        })
+        .collect();
+
+    json!({
+        "$schema": SARIF_SCHEMA,
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "PhantomTrace",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": env!("CARGO_PKG_REPOSITORY"),
+                    "rules": rules
+                }
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results
+        }]
+    })
+}
+
+fn sarif_level(severity: &TraceSeverity) -> &'static str {
+    match severity {
+        TraceSeverity::Critical | TraceSeverity::High => "error",
+        TraceSeverity::Medium => "warning",
+        TraceSeverity::Low => "note",
+    }
+}
+
+// Timestamp at the start of a log line, e.g. `2024-01-01T12:00:00Z` or `2024-01-01 12:00:00`
+fn leading_timestamp(line: &str) -> Option<SystemTime> {
+    let token = line.split_whitespace().next()?;
+    humantime::parse_rfc3339_weak(token)
+        .ok()
+        .or_else(|| humantime::parse_rfc3339_weak(line.get(..19)?).ok())
+}
+
+fn epoch_seconds(time: SystemTime) -> f64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    // Millisecond precision, as HEC expects
+    (since_epoch.as_millis() as f64) / 1000.0
+}
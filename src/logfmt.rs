@@ -0,0 +1,102 @@
+//! logfmt (`level=info user=alice msg="signed in"`) parsing and serialization
+
+/// One `key=value` pair
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pair {
+    pub key: String,
+    pub value: String, // Unescaped
+    pub quoted: bool,  // Written as `"..."` in the input; kept quoted on output
+}
+
+/// Parse a logfmt line. `None` unless every whitespace-separated token is a `key=value`
+/// pair: a bare word could be free text, and free text must go through the text rules.
+pub fn parse(line: &str) -> Option<Vec<Pair>> {
+    let mut pairs = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|&c| c != '=' && !c.is_whitespace()) {
+            if c == '"' {
+                return None;
+            }
+            key.push(c);
+        }
+        if key.is_empty() || chars.next() != Some('=') {
+            return None;
+        }
+
+        let mut value = String::new();
+        let quoted = chars.next_if_eq(&'"').is_some();
+        if quoted {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => match chars.next()? {
+                        'n' => value.push('\n'),
+                        'r' => value.push('\r'),
+                        't' => value.push('\t'),
+                        escaped @ ('"' | '\\') => value.push(escaped),
+                        other => {
+                            value.push('\\');
+                            value.push(other);
+                        }
+                    },
+                    c => value.push(c),
+                }
+            }
+            // A closing quote must end the token
+            if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                return None;
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                if c == '"' {
+                    return None;
+                }
+                value.push(c);
+            }
+        }
+        pairs.push(Pair { key, value, quoted });
+    }
+
+    (!pairs.is_empty()).then_some(pairs)
+}
+
+/// Write pairs back as one line, quoting any value that needs it
+pub fn serialize(pairs: &[Pair]) -> String {
+    let mut line = String::new();
+    for pair in pairs {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&pair.key);
+        line.push('=');
+        if pair.quoted || pair.value.chars().any(needs_quotes) {
+            line.push('"');
+            for c in pair.value.chars() {
+                match c {
+                    '"' => line.push_str("\\\""),
+                    '\\' => line.push_str("\\\\"),
+                    '\n' => line.push_str("\\n"),
+                    '\r' => line.push_str("\\r"),
+                    '\t' => line.push_str("\\t"),
+                    c => line.push(c),
+                }
+            }
+            line.push('"');
+        } else {
+            line.push_str(&pair.value);
+        }
+    }
+    line
+}
+
+fn needs_quotes(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || c == '=' || c == '"'
+}
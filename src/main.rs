@@ -1,11 +1,18 @@
 use clap::{Arg, ArgAction, ArgMatches, Command};
-use phantomtrace::{PhantomTraceConfig, PhantomTraceProcessor};
-use std::io::{self, BufRead, BufReader, Write};
-use std::net::{TcpListener, TcpStream};
+use phantomtrace::{
+    benchmark::{self, BenchmarkLimit},
+    directory::{self, DirectoryOptions},
+    health_server,
+    processor::ProcessingStats,
+    stream_processor::StreamProcessor,
+    tcp_server, PhantomTraceConfig, PhantomTraceProcessor, TraceMapKey,
+};
+use std::io::{self, BufRead, IsTerminal};
+use std::net::TcpListener;
 use std::path::Path;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc,
+    Arc, Mutex,
 };
 use std::thread;
 use std::time::Duration;
@@ -13,6 +20,7 @@ use std::time::Duration;
 struct PhantomTraceApp {
     config: PhantomTraceConfig,
     shutdown_signal: Arc<AtomicBool>,
+    stats_sink: Option<Arc<Mutex<ProcessingStats>>>, // Live stats for a health server running alongside
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -28,6 +36,18 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         return handle_version_info();
     }
 
+    if let Some(config_path) = matches.get_one::<String>("validate-config") {
+        return handle_config_validation(config_path, &matches);
+    }
+
+    if matches.get_flag("emit-schema") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&PhantomTraceConfig::json_schema())?
+        );
+        return Ok(());
+    }
+
     if matches.get_flag("health-check") {
         return handle_health_check();
     }
@@ -35,14 +55,35 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let config = load_configuration(&matches)?;
     validate_configuration(&config)?;
 
-    let app = PhantomTraceApp {
+    if matches.get_flag("list-rules") {
+        return handle_list_rules(&config, &matches);
+    }
+
+    if matches.get_flag("explain") {
+        return explain_mode(&config);
+    }
+
+    if matches.get_flag("benchmark") {
+        return benchmark_mode(&config, &matches);
+    }
+
+    let mut app = PhantomTraceApp {
         config: config.clone(),
         shutdown_signal: Arc::new(AtomicBool::new(false)),
+        stats_sink: None,
     };
 
     setup_signal_handlers(app.shutdown_signal.clone())?;
 
-    match determine_operation_mode(&matches) {
+    let mode = determine_operation_mode(&matches);
+    // With --stream or --tcp-server the health server reports that mode's live stats
+    if let (OperationMode::StreamProcessor | OperationMode::TcpServer(_), Some(port)) =
+        (&mode, health_server_port(&matches))
+    {
+        app.stats_sink = Some(spawn_health_server(&app, port)?);
+    }
+
+    match mode {
         OperationMode::StreamProcessor => stream_mode(&app, &matches),
         OperationMode::TcpServer(port) => tcp_server_mode(&app, port),
         OperationMode::FileMonitor(path) => file_monitor_mode(&app, &path),
@@ -61,10 +102,12 @@ fn build_cli_parser() -> ArgMatches {
             .short('i')
             .long("input")
             .value_name("FILE")
-            .help("Input file to process")
+            .help("Input file to process (repeat for several files; --output is then a directory)")
+            .action(ArgAction::Append)
             .required_unless_present_any([
                 "stream", "tcp-server", "monitor", "generate-config",
-                "health-check", "health-server", "version-info"
+                "health-check", "health-server", "version-info", "list-rules", "explain",
+                "input-dir", "benchmark", "emit-schema", "validate-config"
             ]))
 
         .arg(Arg::new("output")
@@ -72,11 +115,54 @@ fn build_cli_parser() -> ArgMatches {
             .long("output")
             .value_name("FILE")
             .help("Output file for processed data")
+            .conflicts_with("input-dir")
             .required_unless_present_any([
                 "stream", "tcp-server", "monitor", "generate-config",
-                "health-check", "health-server", "version-info"
+                "health-check", "health-server", "version-info", "list-rules", "explain",
+                "input-dir", "benchmark", "emit-schema", "validate-config"
             ]))
 
+        .arg(Arg::new("input-dir")
+            .long("input-dir")
+            .value_name("DIR")
+            .help("Process every file in a directory into --output-dir, keeping relative paths")
+            .conflicts_with("input")
+            .requires("output-dir"))
+
+        .arg(Arg::new("output-dir")
+            .long("output-dir")
+            .value_name("DIR")
+            .help("Output directory for --input-dir")
+            .requires("input-dir"))
+
+        .arg(Arg::new("recursive")
+            .short('r')
+            .long("recursive")
+            .help("Descend into subdirectories of --input-dir")
+            .action(ArgAction::SetTrue))
+
+        .arg(Arg::new("glob")
+            .long("glob")
+            .value_name("PATTERN")
+            .help("Only process --input-dir files whose name matches, e.g. '*.log'"))
+
+        .arg(Arg::new("benchmark")
+            .long("benchmark")
+            .help("Measure throughput of the configured rules on a synthetic corpus (or --input)")
+            .action(ArgAction::SetTrue))
+
+        .arg(Arg::new("benchmark-lines")
+            .long("benchmark-lines")
+            .value_name("N")
+            .help("Lines to process in --benchmark (default 100000)")
+            .value_parser(clap::value_parser!(usize)))
+
+        .arg(Arg::new("benchmark-seconds")
+            .long("benchmark-seconds")
+            .value_name("SECONDS")
+            .help("Stop --benchmark after this long instead of after a line count")
+            .value_parser(clap::value_parser!(u64)))
+
         .arg(Arg::new("config")
             .short('c')
             .long("config")
@@ -91,7 +177,7 @@ fn build_cli_parser() -> ArgMatches {
         .arg(Arg::new("config-preset")
             .long("config-preset")
             .value_name("PRESET")
-            .help("Configuration preset: default, splunk, elk, high-performance")
+            .help("Configuration preset: default, splunk, elk, high-performance, strict-pci")
             .default_value("default"))
 
         .arg(Arg::new("stream")
@@ -114,14 +200,14 @@ fn build_cli_parser() -> ArgMatches {
         .arg(Arg::new("health-server")
             .long("health-server")
             .value_name("PORT")
-            .help("Run health check server (default: 8080)")
-            .conflicts_with_all(["stream", "tcp-server", "monitor"]))
+            .help("Run health check server (default: 8080); with --stream or --tcp-server, alongside it")
+            .conflicts_with("monitor"))
 
         .arg(Arg::new("format")
             .short('f')
             .long("format")
             .value_name("FORMAT")
-            .help("Output format: text, json, csv, trace-report")
+            .help("Output format: text, json, json-lines, csv, trace-report, splunk-hec, elk, sarif")
             .default_value("text"))
 
         .arg(Arg::new("splunk-mode")
@@ -173,6 +259,26 @@ fn build_cli_parser() -> ArgMatches {
             .help("Show version information")
             .action(ArgAction::SetTrue))
 
+        .arg(Arg::new("validate-config")
+            .long("validate-config")
+            .value_name("FILE")
+            .help("Check a configuration file (schema, rule patterns, rule names) and exit"))
+
+        .arg(Arg::new("emit-schema")
+            .long("emit-schema")
+            .help("Print the configuration file's JSON Schema, for editor validation and completion")
+            .action(ArgAction::SetTrue))
+
+        .arg(Arg::new("list-rules")
+            .long("list-rules")
+            .help("List configured rules after presets and overrides (--format json for JSON)")
+            .action(ArgAction::SetTrue))
+
+        .arg(Arg::new("explain")
+            .long("explain")
+            .help("Show what each rule matches in text read from stdin, without phantoming it")
+            .action(ArgAction::SetTrue))
+
         .arg(Arg::new("trace-report")
             .long("trace-report")
             .help("Include detailed trace report")
@@ -188,6 +294,22 @@ fn build_cli_parser() -> ArgMatches {
             .help("Create processing trace map")
             .action(ArgAction::SetTrue))
 
+        .arg(Arg::new("min-severity")
+            .long("min-severity")
+            .value_name("LEVEL")
+            .help("Only apply rules at or above: critical, high, medium, low"))
+
+        .arg(Arg::new("disable-rule")
+            .long("disable-rule")
+            .value_name("NAME")
+            .help("Disable a rule by name (repeatable)")
+            .action(ArgAction::Append))
+
+        .arg(Arg::new("trace-map-key-file")
+            .long("trace-map-key-file")
+            .value_name("FILE")
+            .help("Hex-encoded 256-bit key; writes an encrypted, reversible trace map"))
+
         .arg(Arg::new("quiet")
             .short('q')
             .long("quiet")
@@ -214,14 +336,19 @@ fn determine_operation_mode(matches: &ArgMatches) -> OperationMode {
         OperationMode::TcpServer(port)
     } else if let Some(file_path) = matches.get_one::<String>("monitor") {
         OperationMode::FileMonitor(file_path.to_string())
-    } else if let Some(port_str) = matches.get_one::<String>("health-server") {
-        let port = port_str.parse().unwrap_or(8080);
+    } else if let Some(port) = health_server_port(matches) {
         OperationMode::HealthServer(port)
     } else {
         OperationMode::BatchProcessor
     }
 }
 
+fn health_server_port(matches: &ArgMatches) -> Option<u16> {
+    matches
+        .get_one::<String>("health-server")
+        .map(|port_str| port_str.parse().unwrap_or(8080))
+}
+
 fn load_configuration(
     matches: &ArgMatches,
 ) -> Result<PhantomTraceConfig, Box<dyn std::error::Error>> {
@@ -229,12 +356,7 @@ fn load_configuration(
         PhantomTraceConfig::load_from_file(config_path)?
     } else {
         let preset = matches.get_one::<String>("config-preset").unwrap();
-        match preset.as_str() {
-            "splunk" => PhantomTraceConfig::splunk_preset(),
-            "elk" => PhantomTraceConfig::elk_preset(),
-            "high-performance" => PhantomTraceConfig::high_performance_preset(),
-            _ => PhantomTraceConfig::default(),
-        }
+        PhantomTraceConfig::from_preset(preset)?
     };
 
     apply_cli_overrides(&mut config, matches)?;
@@ -248,8 +370,13 @@ fn apply_cli_overrides(
     if let Some(format) = matches.get_one::<String>("format") {
         config.output.format = match format.as_str() {
             "json" => phantomtrace::config::OutputFormat::Json,
+            "json-lines" | "jsonl" => phantomtrace::config::OutputFormat::JsonLines,
+            "json-array" => phantomtrace::config::OutputFormat::JsonArray,
+            "sarif" => phantomtrace::config::OutputFormat::Sarif,
             "csv" => phantomtrace::config::OutputFormat::Csv,
             "trace-report" => phantomtrace::config::OutputFormat::TraceReport,
+            "splunk-hec" => phantomtrace::config::OutputFormat::SplunkHec,
+            "elk" => phantomtrace::config::OutputFormat::Elk,
             _ => phantomtrace::config::OutputFormat::Text,
         };
     }
@@ -257,7 +384,14 @@ fn apply_cli_overrides(
     if matches.get_flag("splunk-mode") {
         config.preprocessing.splunk_integration.enabled = true;
         config.preprocessing.mode = phantomtrace::config::PreprocessingMode::StreamProcessor;
-        config.output.format = phantomtrace::config::OutputFormat::Json;
+        config.output.format = phantomtrace::config::OutputFormat::SplunkHec;
+        config.output.include_trace_report = false;
+    }
+
+    if matches.get_flag("elk-mode") {
+        config.preprocessing.elk_integration.enabled = true;
+        config.preprocessing.mode = phantomtrace::config::PreprocessingMode::StreamProcessor;
+        config.output.format = phantomtrace::config::OutputFormat::Elk;
         config.output.include_trace_report = false;
     }
 
@@ -267,6 +401,10 @@ fn apply_cli_overrides(
         config.preprocessing.performance_tuning.async_processing = true;
     }
 
+    if matches.get_flag("metrics") {
+        config.monitoring.metrics_enabled = true;
+    }
+
     if matches.get_flag("trace-report") {
         config.output.include_trace_report = true;
     }
@@ -279,6 +417,32 @@ fn apply_cli_overrides(
         config.output.create_trace_map = true;
     }
 
+    if let Some(level) = matches.get_one::<String>("min-severity") {
+        config.processing.min_severity = level.parse()?;
+    }
+
+    disable_rules(config, matches)?;
+
+    if matches.contains_id("trace-map-key-file") {
+        config.output.create_trace_map = true;
+        config.output.reversible_trace_map = true;
+    }
+
+    Ok(())
+}
+
+// Apply every `--disable-rule`, reporting the first unknown name
+fn disable_rules(
+    config: &mut PhantomTraceConfig,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    for name in matches
+        .get_many::<String>("disable-rule")
+        .into_iter()
+        .flatten()
+    {
+        config.disable_rule(name)?;
+    }
     Ok(())
 }
 
@@ -298,83 +462,188 @@ fn stream_mode(
     _app: &PhantomTraceApp,
     _matches: &ArgMatches,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut processor = PhantomTraceProcessor::new(_app.config.clone())?;
-    let stdin = io::stdin();
-    let stdout = io::stdout();
-    let mut stdout_lock = stdout.lock();
-
-    for line in stdin.lock().lines() {
-        let line = line?;
-        let result = processor.phantom_text(&line);
-        writeln!(stdout_lock, "{}", result.phantomed_text)?;
-        stdout_lock.flush()?;
+    let mut config = _app.config.clone();
+    // Write each line as it arrives rather than holding a batch back from a live pipe
+    config.processing.batch_size = 1;
+    let mut stream = StreamProcessor::new(config)?;
+    stream.set_shutdown(_app.shutdown_signal.clone());
+    if let Some(sink) = &_app.stats_sink {
+        stream.processor_mut().set_stats_sink(sink.clone());
     }
 
+    let stdout = io::stdout();
+    if let Err(e) = stream.process_reader(io::stdin().lock(), stdout.lock()) {
+        // Still close a JSON array so the output written so far parses
+        let _ = stream.finish(stdout.lock());
+        return Err(e.into());
+    }
     Ok(())
 }
 
 fn tcp_server_mode(_app: &PhantomTraceApp, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let mut template = PhantomTraceProcessor::new(_app.config.clone())?;
+    if let Some(sink) = &_app.stats_sink {
+        template.set_stats_sink(sink.clone());
+    }
+
+    #[cfg(feature = "tokio")]
+    if _app
+        .config
+        .preprocessing
+        .performance_tuning
+        .async_processing
+    {
+        let runtime = phantomtrace::async_server::runtime(
+            _app.config
+                .preprocessing
+                .performance_tuning
+                .thread_pool_size,
+        )?;
+        runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+            eprintln!("PhantomTrace async TCP server listening on port {}", port);
+            let shutdown = _app.shutdown_signal.clone();
+            phantomtrace::async_server::serve_processor(template, listener, shutdown).await
+        })?;
+        return Ok(());
+    }
+
     let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
     eprintln!("PhantomTrace TCP server listening on port {}", port);
 
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
-                let config = _app.config.clone();
-                thread::spawn(move || {
-                    if let Err(e) = handle_tcp_client(stream, &config) {
-                        eprintln!("Client error: {}", e);
-                    }
-                });
-            }
-            Err(e) => eprintln!("Connection failed: {}", e),
-        }
-    }
+    tcp_server::serve_processor(
+        template,
+        listener,
+        _app.shutdown_signal.clone(),
+        tcp_server::SHUTDOWN_GRACE_PERIOD,
+    )?;
+    eprintln!("PhantomTrace TCP server stopped");
 
     Ok(())
 }
 
-fn handle_tcp_client(
-    stream: TcpStream,
-    config: &PhantomTraceConfig,
+fn batch_mode(
+    _app: &PhantomTraceApp,
+    matches: &ArgMatches,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut processor = PhantomTraceProcessor::new(config.clone())?;
-    let mut write_stream = stream.try_clone()?;
-    let read_stream = stream;
-    let reader = BufReader::new(read_stream);
+    if let Some(input_dir) = matches.get_one::<String>("input-dir") {
+        return directory_mode(_app, matches, input_dir);
+    }
+    let input_paths: Vec<&String> = matches.get_many::<String>("input").unwrap().collect();
+    let output_arg = matches.get_one::<String>("output").unwrap();
 
-    for line in reader.lines() {
-        let line = line?;
-        let result = processor.phantom_text(&line);
-        writeln!(write_stream, "{}", result.phantomed_text)?;
+    for input_path in &input_paths {
+        if !Path::new(input_path).exists() {
+            return Err(format!("Input file '{}' does not exist", input_path).into());
+        }
+    }
+    let multi_file = input_paths.len() > 1;
+    if multi_file {
+        std::fs::create_dir_all(output_arg)?;
+    }
+
+    let create_trace_map = _app.config.output.create_trace_map;
+    let quiet = matches.get_flag("quiet");
+
+    // Per-file numbers must not include earlier files
+    let mut config = _app.config.clone();
+    if multi_file {
+        config.processing.reset_stats_per_file = true;
+        config.processing.keep_grand_total = true;
+    }
+    let mut processor = PhantomTraceProcessor::new(config)?;
+    if let Some(key_path) = matches.get_one::<String>("trace-map-key-file") {
+        let key = TraceMapKey::from_hex(&std::fs::read_to_string(key_path)?)?;
+        processor.set_trace_map_key(key);
+    }
+
+    for input_path in input_paths {
+        let output_path = if multi_file {
+            let file_name = Path::new(input_path)
+                .file_name()
+                .ok_or_else(|| format!("Input '{}' is not a file", input_path))?;
+            Path::new(output_arg)
+                .join(file_name)
+                .to_string_lossy()
+                .into_owned()
+        } else {
+            output_arg.clone()
+        };
+
+        if !quiet {
+            eprintln!("Processing: {} -> {}", input_path, output_path);
+        }
+        let result = processor.phantom_file(input_path, &output_path)?;
+        if !quiet {
+            display_results(&result, &output_path, &processor, create_trace_map);
+        }
+    }
+
+    if multi_file && !quiet {
+        if let Some(total) = processor.grand_total_stats() {
+            eprintln!("All files");
+            eprintln!("Lines processed: {}", total.lines_processed);
+            eprintln!("Lines modified: {}", total.lines_phantomed);
+            eprintln!("Events: {}", total.total_phantom_events);
+            eprintln!("Processing time: {:?}", total.processing_time);
+        }
     }
 
     Ok(())
 }
 
-fn batch_mode(
+fn directory_mode(
     _app: &PhantomTraceApp,
     matches: &ArgMatches,
+    input_dir: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let input_path = matches.get_one::<String>("input").unwrap();
-    let output_path = matches.get_one::<String>("output").unwrap();
-
-    if !Path::new(input_path).exists() {
-        return Err(format!("Input file '{}' does not exist", input_path).into());
-    }
-
+    let output_dir = matches.get_one::<String>("output-dir").unwrap();
+    let options = DirectoryOptions {
+        recursive: matches.get_flag("recursive"),
+        glob: matches.get_one::<String>("glob").cloned(),
+    };
     let create_trace_map = _app.config.output.create_trace_map;
     let quiet = matches.get_flag("quiet");
 
-    if !quiet {
-        eprintln!("Processing: {} -> {}", input_path, output_path);
+    let mut config = _app.config.clone();
+    config.processing.reset_stats_per_file = true;
+    config.processing.keep_grand_total = true;
+    let mut processor = PhantomTraceProcessor::new(config)?;
+    if let Some(key_path) = matches.get_one::<String>("trace-map-key-file") {
+        let key = TraceMapKey::from_hex(&std::fs::read_to_string(key_path)?)?;
+        processor.set_trace_map_key(key);
     }
 
-    let mut processor = PhantomTraceProcessor::new(_app.config.clone())?;
-    let result = processor.phantom_file(input_path, output_path)?;
+    let files = directory::phantom_directory(
+        &mut processor,
+        Path::new(input_dir),
+        Path::new(output_dir),
+        &options,
+        |input_path, output_path, result, processor| {
+            if !quiet {
+                eprintln!(
+                    "Processed: {} -> {}",
+                    input_path.display(),
+                    output_path.display()
+                );
+                display_results(
+                    result,
+                    &output_path.to_string_lossy(),
+                    processor,
+                    create_trace_map,
+                );
+            }
+        },
+    )?;
 
     if !quiet {
-        display_results(&result, output_path, &processor, create_trace_map);
+        if let Some(total) = processor.grand_total_stats() {
+            eprintln!("All files ({})", files);
+            eprintln!("Lines processed: {}", total.lines_processed);
+            eprintln!("Lines modified: {}", total.lines_phantomed);
+            eprintln!("Events: {}", total.total_phantom_events);
+            eprintln!("Processing time: {:?}", total.processing_time);
+        }
     }
 
     Ok(())
@@ -389,7 +658,7 @@ fn display_results(
     eprintln!("Processing completed");
     eprintln!("Lines processed: {}", result.lines_processed);
     eprintln!("Lines modified: {}", result.lines_phantomed);
-    eprintln!("Events: {}", result.phantom_events.len());
+    eprintln!("Events: {}", result.total_events);
     eprintln!("Processing time: {:?}", result.processing_time);
     eprintln!("Output: {}", output_path);
 
@@ -433,6 +702,119 @@ fn handle_config_generation(config_path: &str) -> Result<(), Box<dyn std::error:
     Ok(())
 }
 
+fn handle_config_validation(
+    config_path: &str,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let problems: Vec<String> = match PhantomTraceConfig::validate_file(config_path) {
+        Ok(mut config) => match disable_rules(&mut config, matches)
+            .and_then(|()| validate_configuration(&config))
+        {
+            Ok(()) => {
+                let summaries = config.rule_summaries();
+                let enabled = summaries.iter().filter(|rule| rule.enabled).count();
+                println!(
+                    "PASS {}: {} rules ({} enabled)",
+                    config_path,
+                    summaries.len(),
+                    enabled
+                );
+                return Ok(());
+            }
+            Err(e) => vec![e.to_string()],
+        },
+        Err(errors) => errors.iter().map(ToString::to_string).collect(),
+    };
+
+    println!("FAIL {}: {} problem(s)", config_path, problems.len());
+    for problem in &problems {
+        println!("  - {}", problem);
+    }
+    Err(format!("Invalid configuration: {}", config_path).into())
+}
+
+fn handle_list_rules(
+    config: &PhantomTraceConfig,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if matches.get_one::<String>("format").map(String::as_str) == Some("json") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&config.rule_summaries())?
+        );
+    } else {
+        print!("{}", config.rules_table());
+    }
+    Ok(())
+}
+
+fn explain_mode(config: &PhantomTraceConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let processor = PhantomTraceProcessor::new(config.clone())?;
+    let color = io::stdout().is_terminal();
+    let paint = |code: &str, text: &str| {
+        if color {
+            format!("\x1b[{}m{}\x1b[0m", code, text)
+        } else {
+            text.to_string()
+        }
+    };
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        println!("{}", line);
+        for explanation in processor.explain(&line) {
+            for found in &explanation.matches {
+                let (start, _) = found.span;
+                let indent = " ".repeat(line[..start].chars().count());
+                let marker = "^".repeat(found.matched.chars().count().max(1));
+                let outcome = match &found.skipped {
+                    Some(reason) => paint("2", &format!("skipped: {}", reason)),
+                    None => format!("-> {}", found.phantom_value),
+                };
+                println!(
+                    "{}{} {} ({:?}, {:?}) {}",
+                    indent,
+                    paint("33", &marker),
+                    paint("1", &explanation.rule_name),
+                    explanation.severity,
+                    explanation.method,
+                    outcome
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn benchmark_mode(
+    config: &PhantomTraceConfig,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let corpus = match matches.get_one::<String>("input") {
+        Some(path) => std::fs::read_to_string(path)?
+            .lines()
+            .map(str::to_string)
+            .collect(),
+        None => benchmark::synthetic_corpus(10_000),
+    };
+    let duration = matches
+        .get_one::<u64>("benchmark-seconds")
+        .map(|seconds| Duration::from_secs(*seconds));
+    let limit = BenchmarkLimit {
+        lines: match (matches.get_one::<usize>("benchmark-lines"), duration) {
+            (Some(lines), _) => Some(*lines),
+            (None, Some(_)) => None,
+            (None, None) => BenchmarkLimit::default().lines,
+        },
+        duration,
+    };
+
+    let report = benchmark::run_benchmark(config, &corpus, &limit)?;
+    print!("{}", report);
+    Ok(())
+}
+
 fn handle_version_info() -> Result<(), Box<dyn std::error::Error>> {
     println!("PhantomTrace Enterprise Data Protection Platform");
     println!("Version: 1.0.0");
@@ -456,11 +838,7 @@ fn handle_health_check() -> Result<(), Box<dyn std::error::Error>> {
         return Err("No tracing rules configured".into());
     }
 
-    for rule in &config.tracing.rules {
-        if regex::Regex::new(&rule.pattern).is_err() {
-            return Err(format!("Invalid regex pattern in rule: {}", rule.name).into());
-        }
-    }
+    config.validate()?;
 
     println!("Health check passed");
     println!("Rules validated: {}", config.tracing.rules.len());
@@ -469,18 +847,40 @@ fn handle_health_check() -> Result<(), Box<dyn std::error::Error>> {
 }
 
 fn health_server_mode(_app: &PhantomTraceApp, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
     eprintln!("Health server running on port {}", port);
 
-    loop {
-        if _app.shutdown_signal.load(Ordering::Relaxed) {
-            break;
-        }
-        thread::sleep(Duration::from_secs(1));
-    }
+    // Nothing is processed in this mode, so /metrics stays at zero
+    health_server::serve_listener(
+        listener,
+        Arc::new(Mutex::new(ProcessingStats::default())),
+        _app.config.monitoring.metrics_enabled,
+        _app.shutdown_signal.clone(),
+    )?;
 
     Ok(())
 }
 
+// Serve /health and /metrics on a background thread until shutdown; returns the stats
+// handle the mode's processors should report to
+fn spawn_health_server(
+    _app: &PhantomTraceApp,
+    port: u16,
+) -> Result<Arc<Mutex<ProcessingStats>>, Box<dyn std::error::Error>> {
+    let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
+    eprintln!("Health server running on port {}", port);
+
+    let stats = Arc::new(Mutex::new(ProcessingStats::default()));
+    let (shared, metrics_enabled) = (stats.clone(), _app.config.monitoring.metrics_enabled);
+    let shutdown = _app.shutdown_signal.clone();
+    thread::spawn(move || {
+        if let Err(e) = health_server::serve_listener(listener, shared, metrics_enabled, shutdown) {
+            eprintln!("Health server error: {}", e);
+        }
+    });
+    Ok(stats)
+}
+
 fn file_monitor_mode(
     _app: &PhantomTraceApp,
     file_path: &str,
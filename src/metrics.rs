@@ -0,0 +1,102 @@
+use crate::processor::ProcessingStats;
+use serde::Serialize;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Upper bounds, in seconds, of the per-call processing time histogram
+pub const PROCESSING_TIME_BUCKETS: [f64; 9] =
+    [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0];
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcessingTimeHistogram {
+    pub bucket_counts: [u64; PROCESSING_TIME_BUCKETS.len()], // Per bucket, not cumulative
+    pub count: u64,
+    pub sum_seconds: f64,
+}
+
+impl ProcessingTimeHistogram {
+    pub fn merge(&mut self, other: &ProcessingTimeHistogram) {
+        for (count, other_count) in self.bucket_counts.iter_mut().zip(other.bucket_counts) {
+            *count += other_count;
+        }
+        self.count += other.count;
+        self.sum_seconds += other.sum_seconds;
+    }
+
+    pub fn observe(&mut self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = PROCESSING_TIME_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+        {
+            self.bucket_counts[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum_seconds += seconds;
+    }
+}
+
+/// Render cumulative processing stats in the Prometheus text exposition format
+pub fn render_prometheus(stats: &ProcessingStats) -> String {
+    let mut out = String::new();
+
+    let counters = [
+        (
+            "phantomtrace_lines_processed_total",
+            "Lines read by the processor",
+            stats.lines_processed,
+        ),
+        (
+            "phantomtrace_lines_phantomed_total",
+            "Lines with at least one phantom event",
+            stats.lines_phantomed,
+        ),
+        (
+            "phantomtrace_bytes_processed_total",
+            "Input bytes processed",
+            stats.bytes_processed,
+        ),
+    ];
+    for (name, help, value) in counters {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} counter", name);
+        let _ = writeln!(out, "{} {}", name, value);
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP phantomtrace_events_total Phantom events by rule and severity"
+    );
+    let _ = writeln!(out, "# TYPE phantomtrace_events_total counter");
+    for (rule_name, (severity, events)) in &stats.events_by_rule {
+        let _ = writeln!(
+            out,
+            "phantomtrace_events_total{{severity=\"{:?}\",rule=\"{}\"}} {}",
+            severity,
+            escape_label(rule_name),
+            events
+        );
+    }
+
+    let histogram = &stats.processing_time_histogram;
+    let name = "phantomtrace_processing_duration_seconds";
+    let _ = writeln!(out, "# HELP {} Time spent per phantom_text call", name);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+    let mut cumulative = 0;
+    for (bound, count) in PROCESSING_TIME_BUCKETS.iter().zip(histogram.bucket_counts) {
+        cumulative += count;
+        let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative);
+    }
+    let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count);
+    let _ = writeln!(out, "{}_sum {}", name, histogram.sum_seconds);
+    let _ = writeln!(out, "{}_count {}", name, histogram.count);
+
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,13 +1,42 @@
-use crate::config::{OutputFormat, PhantomTraceConfig};
-use crate::tracer::{PhantomEvent, PhantomTracer, TraceReport};
-use serde::Serialize;
-use std::time::Instant;
+use crate::ansi;
+use crate::audit::{self, AuditLog};
+use crate::clock::{Clock, Instant};
+use crate::config::{
+    OutputFormat, OversizedLinePolicy, PhantomTraceConfig, TraceRule, TraceSeverity,
+};
+use crate::encoding;
+use crate::error::PhantomError;
+use crate::event_buffer::EventBuffer;
+use crate::integrations;
+use crate::logfmt;
+use crate::metrics::ProcessingTimeHistogram;
+use crate::syslog;
+use crate::trace_map::{self, EncryptedReversal, ReversalTable, TraceMapKey};
+use crate::tracer::{
+    CompileStats, ObfuscationHook, PhantomEvent, PhantomTracer, RuleMatchExplanation, TraceReport,
+};
+use crate::url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Appended where `OversizedLinePolicy::Truncate` cut a line short
+pub const TRUNCATED_LINE_MARKER: &str = "[LINE_TRUNCATED]";
 
 #[derive(Debug, Clone)]
 pub struct PhantomTraceProcessor {
     pub(crate) config: PhantomTraceConfig,
     tracer: PhantomTracer,
     processing_stats: ProcessingStats,
+    trace_map_key: Option<TraceMapKey>,
+    audit_log: Option<AuditLog>,
+    event_buffer: Option<EventBuffer>,
+    grand_total: Option<ProcessingStats>, // Stats of earlier files when keep_grand_total is set
+    rules_compiled: bool,                 // False until a `new_lazy` processor compiles its rules
+    stats_sink: Option<Arc<Mutex<ProcessingStats>>>, // Also receives every counter update
 }
 
 #[derive(Debug, Default, Clone)]
@@ -15,165 +44,1183 @@ pub struct ProcessingStats {
     pub lines_processed: u64,
     pub lines_phantomed: u64,
     pub total_phantom_events: u64,
+    pub bytes_processed: u64,
     pub processing_time: std::time::Duration,
     pub start_time: Option<Instant>,
+    pub events_by_rule: BTreeMap<String, (TraceSeverity, u64)>,
+    pub processing_time_histogram: ProcessingTimeHistogram,
+}
+
+impl ProcessingStats {
+    /// Add `other`'s counters to these stats
+    pub fn merge(&mut self, other: &ProcessingStats) {
+        self.lines_processed += other.lines_processed;
+        self.lines_phantomed += other.lines_phantomed;
+        self.total_phantom_events += other.total_phantom_events;
+        self.bytes_processed += other.bytes_processed;
+        self.processing_time += other.processing_time;
+        self.start_time = match (self.start_time, other.start_time) {
+            (Some(ours), Some(theirs)) => Some(ours.min(theirs)),
+            (ours, theirs) => ours.or(theirs),
+        };
+        for (rule_name, (severity, events)) in &other.events_by_rule {
+            self.events_by_rule
+                .entry(rule_name.clone())
+                .or_insert_with(|| (severity.clone(), 0))
+                .1 += events;
+        }
+        self.processing_time_histogram
+            .merge(&other.processing_time_histogram);
+    }
 }
 
 impl PhantomTraceProcessor {
-    pub fn new(config: PhantomTraceConfig) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(mut config: PhantomTraceConfig) -> Result<Self, PhantomError> {
+        config.merge_rule_packs()?;
+        let tracer = Self::build_tracer(&config)?;
+        Self::with_tracer(config, tracer, true)
+    }
+
+    /// Like `new`, but rule patterns are compiled by `precompile` or on first use instead
+    /// of here. The config is validated now, so a bad pattern is reported here rather
+    /// than on first use. `explain` finds nothing until the rules are compiled.
+    pub fn new_lazy(mut config: PhantomTraceConfig) -> Result<Self, PhantomError> {
+        config.merge_rule_packs()?;
+        config.validate()?;
+        let placeholder = PhantomTracer::new(&[], config.tracing.case_sensitive)?;
+        Self::with_tracer(config, placeholder, false)
+    }
+
+    /// Compile the rules now if they aren't yet, and report how long each took
+    pub fn precompile(&mut self) -> Result<CompileStats, PhantomError> {
+        if !self.rules_compiled {
+            self.tracer = Self::build_tracer(&self.config)?.with_overrides_of(&self.tracer);
+            self.rules_compiled = true;
+        }
+        Ok(self.tracer.compile_stats())
+    }
+
+    fn ensure_compiled(&mut self) {
+        if !self.rules_compiled {
+            if let Err(e) = self.precompile() {
+                panic!("Lazy rule compilation failed: {}", e);
+            }
+        }
+    }
+
+    fn build_tracer(config: &PhantomTraceConfig) -> Result<PhantomTracer, PhantomError> {
         // Updated: pass case_sensitive parameter to PhantomTracer::new
-        let tracer = PhantomTracer::new(&config.tracing.rules, config.tracing.case_sensitive)?;
+        let active_rules: Vec<TraceRule> = config
+            .effective_rules()
+            .into_iter()
+            .filter(|rule| rule.severity >= config.processing.min_severity)
+            .collect();
+        let tracer = PhantomTracer::with_limits(
+            &active_rules,
+            config.tracing.case_sensitive,
+            config.tracing.unicode_boundaries,
+            &config.tracing.regex_limits(),
+        )?
+        .with_mask_char(config.processing.mask_char)
+        .with_grapheme_masking(config.processing.grapheme_masking)
+        .with_min_confidence(config.processing.min_confidence)
+        .with_rule_order(&config.processing.rule_order)
+        .with_max_matches_per_line(config.processing.max_matches_per_line)
+        .with_deterministic_trace_ids(config.processing.deterministic_trace_ids)
+        .with_shared_token_map(config.processing.shared_token_map)
+        .with_sample_matches(config.output.sample_matches)
+        .with_allowlist(
+            &config.tracing.allowlist,
+            &config.tracing.allowlist_patterns,
+        )?
+        .with_key_redaction(
+            &config.tracing.redact_keys,
+            config.tracing.redact_method.clone(),
+        )?
+        .with_url_param_redaction(
+            config.processing.url_aware,
+            config.tracing.redact_method.clone(),
+        )?;
+        Ok(tracer)
+    }
+
+    fn with_tracer(
+        config: PhantomTraceConfig,
+        tracer: PhantomTracer,
+        rules_compiled: bool,
+    ) -> Result<Self, PhantomError> {
+        if !config.processing.csv_delimiter.is_ascii() {
+            return Err(PhantomError::Config(
+                "CSV delimiter must be an ASCII character".into(),
+            ));
+        }
+        let salt = if config.monitoring.audit_include_hash {
+            Some(config.monitoring.audit_hash_salt.clone().ok_or_else(|| {
+                PhantomError::Config(
+                    "audit_include_hash requires monitoring.audit_hash_salt".into(),
+                )
+            })?)
+        } else {
+            None
+        };
+        let audit_log = if config.monitoring.audit_logging {
+            Some(AuditLog::open(
+                &config.monitoring.audit_log_path,
+                salt.clone(),
+            )?)
+        } else {
+            None
+        };
+        let event_buffer = match &config.monitoring.event_buffer_path {
+            Some(path) => Some(EventBuffer::open(
+                path,
+                salt,
+                Duration::from_millis(config.preprocessing.performance_tuning.flush_interval_ms),
+            )?),
+            None => None,
+        };
+
+        let grand_total = config
+            .processing
+            .keep_grand_total
+            .then(ProcessingStats::default);
+
         Ok(Self {
             config,
             tracer,
             processing_stats: ProcessingStats::default(),
+            trace_map_key: None,
+            audit_log,
+            event_buffer,
+            grand_total,
+            rules_compiled,
+            stats_sink: None,
         })
     }
 
+    /// A processor with fresh stats sharing this one's config and compiled rules.
+    /// Cheap enough to create per connection.
+    pub fn session(&self) -> Self {
+        Self {
+            config: self.config.clone(),
+            tracer: self.tracer.session(),
+            processing_stats: ProcessingStats::default(),
+            trace_map_key: self.trace_map_key.clone(),
+            audit_log: self.audit_log.clone(),
+            event_buffer: self.event_buffer.clone(),
+            grand_total: self
+                .grand_total
+                .as_ref()
+                .map(|_| ProcessingStats::default()),
+            rules_compiled: self.rules_compiled,
+            stats_sink: self.stats_sink.clone(),
+        }
+    }
+
+    /// Also add every counter update to `sink`, so another thread (the health server's
+    /// `/metrics`) can read live totals. Sessions and clones of this processor share it.
+    pub fn set_stats_sink(&mut self, sink: Arc<Mutex<ProcessingStats>>) {
+        self.stats_sink = Some(sink);
+    }
+
+    /// Consulted for every match; `Some(value)` overrides the configured method's output
+    pub fn set_obfuscation_hook(&mut self, hook: ObfuscationHook) {
+        self.tracer.set_obfuscation_hook(hook);
+    }
+
+    /// Time source for trace IDs, rule stats and reports; the system clock by default
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.tracer.set_clock(clock);
+    }
+
+    /// Key used to encrypt the reversal table when `output.reversible_trace_map` is set
+    pub fn set_trace_map_key(&mut self, key: TraceMapKey) {
+        self.trace_map_key = Some(key);
+    }
+
     pub fn phantom_text(&mut self, input: &str) -> ProcessingResult {
+        self.ensure_compiled();
+        let start_time = Instant::now();
+        if self.processing_stats.start_time.is_none() {
+            self.processing_stats.start_time = Some(start_time);
+        }
+
+        let pass = if self.config.processing.csv_aware {
+            match self.phantom_csv(input) {
+                Ok(pass) => pass,
+                Err(e) => {
+                    eprintln!("Input is not valid CSV ({}); processed as plain text", e);
+                    self.phantom_lines(input)
+                }
+            }
+        } else if self.config.processing.json_aware {
+            // One (possibly multi-line) document, else JSON lines / text line by line
+            self.phantom_json_document(input)
+                .unwrap_or_else(|| self.phantom_whole_or_lines(input))
+        } else {
+            self.phantom_whole_or_lines(input)
+        };
+
+        let processing_time = start_time.elapsed();
+        let truncated = self.tracer.take_truncated();
+        self.record_pass(&pass, input.len(), processing_time);
+
+        ProcessingResult {
+            phantomed_text: pass.text,
+            total_events: pass.events.seen as usize,
+            events_by_rule: pass.events.by_rule,
+            phantom_events: pass.events.kept,
+            lines_processed: pass.lines_processed,
+            lines_phantomed: pass.lines_phantomed,
+            processing_time,
+            truncated,
+        }
+    }
+
+    /// `phantom_text` with only untagged rules and rules tagged with one of `tags`,
+    /// e.g. `&["pci"]` for a payment service's logs. The processor's own tag filter
+    /// (`set_active_tags`) applies again afterwards.
+    pub fn phantom_text_tagged(&mut self, input: &str, tags: &[&str]) -> ProcessingResult {
+        self.ensure_compiled();
+        let previous = self.tracer.active_tags().map(<[String]>::to_vec);
+        self.tracer
+            .set_active_tags(Some(tags.iter().map(|tag| tag.to_string()).collect()));
+        let result = self.phantom_text(input);
+        self.tracer.set_active_tags(previous);
+        result
+    }
+
+    /// Tag filter for every later call, e.g. per TCP connection; see `phantom_text_tagged`
+    pub fn set_active_tags(&mut self, tags: Option<Vec<String>>) {
+        self.tracer.set_active_tags(tags);
+    }
+
+    /// Phantom a buffer that may contain invalid UTF-8. Rules run line by line over each
+    /// valid run of text; invalid byte sequences are copied to the output untouched.
+    /// Event positions are byte offsets into `input`.
+    pub fn phantom_bytes(&mut self, input: &[u8]) -> (Vec<u8>, Vec<PhantomEvent>) {
+        self.ensure_compiled();
         let start_time = Instant::now();
         if self.processing_stats.start_time.is_none() {
             self.processing_stats.start_time = Some(start_time);
         }
 
-        let lines: Vec<&str> = input.lines().collect();
+        // Unicode patterns only ever match valid UTF-8, so no match can include an invalid
+        // sequence; splitting on them leaves the same matches a bytes regex would find
+        let mut output = Vec::with_capacity(input.len());
+        let mut pass = PhantomPass {
+            text: String::new(),
+            events: self.event_reservoir(),
+            lines_processed: 0,
+            lines_phantomed: 0,
+        };
+        let mut offset = 0;
+        for (index, line) in input.split(|&byte| byte == b'\n').enumerate() {
+            if index > 0 {
+                output.push(b'\n');
+                offset += 1;
+            }
+            let mut line_phantomed = false;
+            for chunk in line.utf8_chunks() {
+                let (phantomed, mut events) = self.tracer.trace_and_phantom(chunk.valid());
+                for event in &mut events {
+                    event.position.0 += offset;
+                    event.position.1 += offset;
+                }
+                line_phantomed |= !events.is_empty();
+                pass.events.extend(events);
+                output.extend_from_slice(phantomed.as_bytes());
+                output.extend_from_slice(chunk.invalid());
+                offset += chunk.valid().len() + chunk.invalid().len();
+            }
+            pass.lines_processed += 1;
+            pass.lines_phantomed += line_phantomed as usize;
+        }
+
+        self.tracer.take_truncated();
+        self.record_pass(&pass, input.len(), start_time.elapsed());
+        (output, pass.events.kept)
+    }
+
+    fn event_reservoir(&self) -> EventReservoir {
+        EventReservoir {
+            kept: Vec::new(),
+            cap: self.reservoir_cap(),
+            seen: 0,
+            by_rule: BTreeMap::new(),
+            audit_log: self.audit_log.clone(),
+            event_buffer: self.event_buffer.clone(),
+            rng_state: RESERVOIR_SEED,
+        }
+    }
+
+    // `max_logged_events` bounds logged events only. Sampling while gathering saves memory
+    // when events are logged, but a reversible trace map and SARIF need every one of
+    // them. Events only counted are left to the event buffer, if there is one.
+    fn reservoir_cap(&self) -> Option<usize> {
+        let output = &self.config.output;
+        let listed = output.log_phantom_events || matches!(output.format, OutputFormat::Csv);
+        if output.reversible_trace_map || matches!(output.format, OutputFormat::Sarif) {
+            None
+        } else if listed {
+            output.max_logged_events
+        } else if self.event_buffer.is_some() {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn record_pass(
+        &mut self,
+        pass: &PhantomPass,
+        input_len: usize,
+        processing_time: std::time::Duration,
+    ) {
+        let mut processing_time_histogram = ProcessingTimeHistogram::default();
+        processing_time_histogram.observe(processing_time);
+        self.add_stats(&ProcessingStats {
+            lines_processed: pass.lines_processed as u64,
+            lines_phantomed: pass.lines_phantomed as u64,
+            total_phantom_events: pass.events.seen,
+            bytes_processed: input_len as u64,
+            processing_time,
+            start_time: None,
+            events_by_rule: pass.events.by_rule.clone(),
+            processing_time_histogram,
+        });
+    }
+
+    fn add_stats(&mut self, stats: &ProcessingStats) {
+        self.processing_stats.merge(stats);
+        if let Some(sink) = &self.stats_sink {
+            // A poisoned lock still holds valid counters
+            sink.lock().unwrap_or_else(|e| e.into_inner()).merge(stats);
+        }
+    }
+
+    /// Phantom each input on its own, returning results in input order.
+    /// Event positions are relative to their own input; global stats still accumulate.
+    pub fn phantom_batch(&mut self, inputs: &[String]) -> Vec<ProcessingResult> {
+        inputs
+            .iter()
+            .map(|input| self.phantom_text(input))
+            .collect()
+    }
+
+    fn phantom_whole_or_lines(&mut self, input: &str) -> PhantomPass {
+        if self.config.processing.document_mode {
+            self.phantom_document(input)
+        } else {
+            self.phantom_lines(input)
+        }
+    }
+
+    // `document_mode`: one rule pass over all of `input`; positions are offsets into it
+    fn phantom_document(&mut self, input: &str) -> PhantomPass {
+        let (text, mut events) = self.phantom_text_line(input);
+        let line_of = |offset: usize| input[..offset].matches('\n').count();
+        let mut touched_lines = std::collections::BTreeSet::new();
+        for event in &mut events {
+            touched_lines.extend(line_of(event.position.0)..=line_of(event.position.1));
+            if self.config.output.event_line_columns {
+                let line_start = input[..event.position.0].rfind('\n').map_or(0, |i| i + 1);
+                event.line_number = Some(line_of(event.position.0) + 1);
+                event.column = Some(input[line_start..event.position.0].chars().count() + 1);
+            }
+        }
+
+        let mut all_events = self.event_reservoir();
+        all_events.extend(events);
+        PhantomPass {
+            text,
+            events: all_events,
+            lines_processed: input.lines().count(),
+            lines_phantomed: touched_lines.len(),
+        }
+    }
+
+    fn phantom_lines(&mut self, input: &str) -> PhantomPass {
         let mut phantomed_lines = Vec::new();
-        let mut all_events = Vec::new();
+        let mut all_events = self.event_reservoir();
         let mut lines_phantomed = 0;
 
-        for line in lines {
-            let (phantomed_line, events) = self.tracer.trace_and_phantom(line);
+        for (line_index, raw_line) in input.lines().enumerate() {
+            let tuning = &self.config.preprocessing.performance_tuning;
+            let (raw_line, truncated) = match tuning.max_line_bytes {
+                Some(limit) if raw_line.len() > limit => match tuning.oversized_lines {
+                    OversizedLinePolicy::PassThrough => {
+                        eprintln!(
+                            "Line of {} bytes exceeds max_line_bytes ({}); passed through unphantomed",
+                            raw_line.len(),
+                            limit
+                        );
+                        phantomed_lines.push(raw_line.to_string());
+                        continue;
+                    }
+                    OversizedLinePolicy::Truncate => {
+                        let mut end = limit;
+                        while !raw_line.is_char_boundary(end) {
+                            end -= 1;
+                        }
+                        (&raw_line[..end], true)
+                    }
+                },
+                _ => (raw_line, false),
+            };
+
+            // Event positions then refer to the line without escape sequences
+            let (stripped, ansi_sequences) =
+                if self.config.processing.strip_ansi && raw_line.contains('\x1b') {
+                    ansi::strip(raw_line)
+                } else {
+                    (String::new(), Vec::new())
+                };
+            let line = if ansi_sequences.is_empty() {
+                raw_line
+            } else {
+                stripped.as_str()
+            };
+
+            let json_line = if self.config.processing.json_aware {
+                self.phantom_json_text(line)
+            } else {
+                None
+            };
+            let logfmt_line = if json_line.is_none() && self.config.processing.logfmt_aware {
+                self.phantom_logfmt_line(line)
+            } else {
+                None
+            };
+            let (phantomed_line, mut events) =
+                if let Some(structured_line) = json_line.or(logfmt_line) {
+                    structured_line
+                } else {
+                    let (phantomed_line, events) = if self.config.processing.syslog_aware {
+                        self.phantom_syslog_line(line)
+                    } else {
+                        self.phantom_text_line(line)
+                    };
+                    if self.config.processing.reinsert_ansi && !ansi_sequences.is_empty() {
+                        // Detect events change nothing and may overlap a redacted span
+                        let changes: Vec<PhantomEvent> = events
+                            .iter()
+                            .filter(|event| !self.tracer.detects_only(&event.rule_name))
+                            .cloned()
+                            .collect();
+                        (ansi::reinsert(line, &changes, &ansi_sequences), events)
+                    } else {
+                        (phantomed_line, events)
+                    }
+                };
+            if self.config.output.event_line_columns {
+                locate_events(&mut events, line, line_index + 1);
+            }
             if !events.is_empty() {
                 lines_phantomed += 1;
                 all_events.extend(events);
             }
-            phantomed_lines.push(phantomed_line);
+            if truncated {
+                phantomed_lines.push(format!("{}{}", phantomed_line, TRUNCATED_LINE_MARKER));
+            } else {
+                phantomed_lines.push(phantomed_line);
+            }
         }
 
-        let processing_time = start_time.elapsed();
+        PhantomPass {
+            lines_processed: phantomed_lines.len(),
+            text: phantomed_lines.join("\n"),
+            events: all_events,
+            lines_phantomed,
+        }
+    }
 
-        // Update stats
-        self.processing_stats.lines_processed += phantomed_lines.len() as u64;
-        self.processing_stats.lines_phantomed += lines_phantomed;
-        self.processing_stats.total_phantom_events += all_events.len() as u64;
-        self.processing_stats.processing_time += processing_time;
+    fn phantom_syslog_line(&mut self, line: &str) -> (String, Vec<PhantomEvent>) {
+        let Some((header, body)) = syslog::split_header(line) else {
+            return self.phantom_text_line(line); // Not syslog, treat as text
+        };
 
-        ProcessingResult {
-            phantomed_text: phantomed_lines.join("\n"),
-            phantom_events: all_events,
-            lines_processed: phantomed_lines.len(),
-            lines_phantomed: lines_phantomed as usize,
-            processing_time,
+        let (phantomed_body, mut events) = self.phantom_text_line(body);
+        // Report positions relative to the whole line
+        for event in &mut events {
+            event.position.0 += header.len();
+            event.position.1 += header.len();
+        }
+        (format!("{}{}", header, phantomed_body), events)
+    }
+
+    // Free text. With `url_aware`, values of sensitive URL query parameters are redacted
+    // whole and the text between them goes through the rules.
+    fn phantom_text_line(&mut self, line: &str) -> (String, Vec<PhantomEvent>) {
+        if !self.config.processing.url_aware {
+            return self.tracer.trace_and_phantom(line);
+        }
+        let sensitive: Vec<url::QueryParam> = url::query_params(line)
+            .into_iter()
+            .filter(|param| {
+                self.config
+                    .processing
+                    .url_sensitive_params
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&param.name))
+            })
+            .collect();
+        if sensitive.is_empty() {
+            return self.tracer.trace_and_phantom(line);
+        }
+
+        let mut phantomed = String::with_capacity(line.len());
+        let mut events = Vec::new();
+        let mut cursor = 0;
+        for param in sensitive {
+            let (start, end) = param.span;
+            let (text, found) = self.tracer.trace_and_phantom(&line[cursor..start]);
+            phantomed.push_str(&text);
+            events.extend(found.into_iter().map(|mut event| {
+                event.position.0 += cursor;
+                event.position.1 += cursor;
+                event
+            }));
+            match self.tracer.redact_url_param(&param.value) {
+                Some((value, mut event)) => {
+                    event.position = (start, end);
+                    if self.config.processing.preserve_structure {
+                        event.field_path = Some(param.name);
+                    }
+                    phantomed.push_str(&url::percent_encode(&value));
+                    events.push(event);
+                }
+                None => phantomed.push_str(&line[start..end]),
+            }
+            cursor = end;
+        }
+        let (text, found) = self.tracer.trace_and_phantom(&line[cursor..]);
+        phantomed.push_str(&text);
+        events.extend(found.into_iter().map(|mut event| {
+            event.position.0 += cursor;
+            event.position.1 += cursor;
+            event
+        }));
+        (phantomed, events)
+    }
+
+    fn phantom_json_document(&mut self, input: &str) -> Option<PhantomPass> {
+        let (text, events) = self.phantom_json_text(input)?;
+        let lines_phantomed = usize::from(!events.is_empty());
+        let mut reservoir = self.event_reservoir();
+        reservoir.extend(events);
+        Some(PhantomPass {
+            text,
+            lines_processed: input.lines().count(),
+            lines_phantomed,
+            events: reservoir,
+        })
+    }
+
+    // JSON-aware processing: rules run on each string or number value so keys, nesting and
+    // escaping survive. `None` when the text isn't a JSON object or array.
+    fn phantom_json_text(&mut self, text: &str) -> Option<(String, Vec<PhantomEvent>)> {
+        let mut document: serde_json::Value = serde_json::from_str(text).ok()?;
+        if !(document.is_object() || document.is_array()) {
+            return None;
+        }
+
+        let mut events = Vec::new();
+        self.phantom_json_value(&mut document, String::new(), &mut events);
+        let output = if text.trim_end().contains('\n') {
+            serde_json::to_string_pretty(&document)
+        } else {
+            serde_json::to_string(&document)
+        }
+        .ok()?;
+        Some((output, events))
+    }
+
+    fn phantom_json_value(
+        &mut self,
+        value: &mut serde_json::Value,
+        path: String,
+        events: &mut Vec<PhantomEvent>,
+    ) {
+        use serde_json::Value;
+
+        let text = match value {
+            Value::String(text) => text.clone(),
+            // Card numbers and the like are often logged as bare numbers
+            Value::Number(number) => number.to_string(),
+            Value::Array(items) => {
+                for (index, item) in items.iter_mut().enumerate() {
+                    self.phantom_json_value(item, format!("{}[{}]", path, index), events);
+                }
+                return;
+            }
+            Value::Object(map) => {
+                for (key, item) in map.iter_mut() {
+                    let child = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    if self.tracer.redacts_key(key) {
+                        self.redact_json_value(item, child, events);
+                    } else {
+                        self.phantom_json_value(item, child, events);
+                    }
+                }
+                return;
+            }
+            Value::Bool(_) | Value::Null => return,
+        };
+
+        let (phantomed, mut found) = self.tracer.trace_and_phantom(&text);
+        if found.is_empty() {
+            return;
+        }
+        if self.config.processing.preserve_structure {
+            for event in &mut found {
+                event.field_path = Some(path.clone());
+            }
+        }
+        // Only Detect events: a number stays a number
+        if phantomed != text {
+            *value = Value::String(phantomed);
+        }
+        events.extend(found);
+    }
+
+    // A redacted key's value is replaced whole; objects and arrays by their JSON text
+    fn redact_json_value(
+        &mut self,
+        value: &mut serde_json::Value,
+        path: String,
+        events: &mut Vec<PhantomEvent>,
+    ) {
+        let text = match &*value {
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Null => return,
+            other => other.to_string(),
+        };
+        if let Some((phantomed, mut event)) = self.tracer.redact_value(&text) {
+            if self.config.processing.preserve_structure {
+                event.field_path = Some(path);
+            }
+            *value = serde_json::Value::String(phantomed);
+            events.push(event);
+        }
+    }
+
+    // logfmt-aware processing: rules run on values (optionally only those of sensitive keys),
+    // so keys and quoting survive. `None` when the line isn't logfmt.
+    fn phantom_logfmt_line(&mut self, line: &str) -> Option<(String, Vec<PhantomEvent>)> {
+        let mut pairs = logfmt::parse(line)?;
+        let mut events = Vec::new();
+
+        for pair in &mut pairs {
+            if self.tracer.redacts_key(&pair.key) {
+                if let Some((phantomed, mut event)) = self.tracer.redact_value(&pair.value) {
+                    if self.config.processing.preserve_structure {
+                        event.field_path = Some(pair.key.clone());
+                    }
+                    pair.value = phantomed;
+                    events.push(event);
+                }
+                continue;
+            }
+            let sensitive_keys = &self.config.processing.logfmt_sensitive_keys;
+            if !sensitive_keys.is_empty()
+                && !sensitive_keys
+                    .iter()
+                    .any(|key| key.eq_ignore_ascii_case(&pair.key))
+            {
+                continue;
+            }
+            let (phantomed, mut found) = self.tracer.trace_and_phantom(&pair.value);
+            if found.is_empty() {
+                continue;
+            }
+            if self.config.processing.preserve_structure {
+                for event in &mut found {
+                    event.field_path = Some(pair.key.clone());
+                }
+            }
+            pair.value = phantomed;
+            events.extend(found);
+        }
+        // Re-serializing normalizes spacing and quoting, so a clean line stays as it was
+        if events.is_empty() {
+            return Some((line.to_string(), events));
+        }
+        Some((logfmt::serialize(&pairs), events))
+    }
+
+    // CSV-aware processing: rules run per field so replacements can't shift columns,
+    // and the writer re-quotes any field whose phantom value contains the delimiter
+    fn phantom_csv(&mut self, input: &str) -> Result<PhantomPass, PhantomError> {
+        // ASCII, checked when the processor was built
+        let delimiter = self.config.processing.csv_delimiter as u8;
+
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(input.as_bytes());
+        let mut writer = csv::WriterBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_writer(Vec::new());
+
+        // Parse everything up front so a malformed row doesn't leave tracer stats half-updated
+        let records = reader.records().collect::<Result<Vec<_>, _>>()?;
+        let mut all_events = self.event_reservoir();
+        let mut rows_phantomed = 0;
+
+        for (index, record) in records.iter().enumerate() {
+            // Header row passes through untouched
+            if index == 0 {
+                writer.write_record(record)?;
+                continue;
+            }
+
+            let mut row_events = Vec::new();
+            let fields: Vec<String> = record
+                .iter()
+                .map(|field| {
+                    let (phantomed, events) = self.tracer.trace_and_phantom(field);
+                    row_events.extend(events);
+                    phantomed
+                })
+                .collect();
+
+            if !row_events.is_empty() {
+                rows_phantomed += 1;
+                all_events.extend(row_events);
+            }
+            writer.write_record(&fields)?;
         }
+
+        let output = writer
+            .into_inner()
+            .map_err(|e| PhantomError::Io(e.into_error()))?;
+        let output = String::from_utf8_lossy(&output).into_owned();
+        Ok(PhantomPass {
+            text: output.trim_end_matches('\n').to_string(),
+            events: all_events,
+            lines_processed: records.len(),
+            lines_phantomed: rows_phantomed,
+        })
     }
 
     pub fn phantom_file(
         &mut self,
         input_path: &str,
         output_path: &str,
-    ) -> Result<ProcessingResult, Box<dyn std::error::Error>> {
-        let input_content = std::fs::read_to_string(input_path)?;
-        let result = self.phantom_text(&input_content);
+    ) -> Result<ProcessingResult, PhantomError> {
+        let output_path = self.confine_output(output_path)?;
+        let output_path = output_path.as_str();
+        // Checked before any output is written
+        let report_path = match &self.config.output.report_path {
+            Some(report_path) => Some(self.confine_output(report_path)?),
+            None => None,
+        };
+        if self.config.processing.reset_stats_per_file {
+            self.start_next_file();
+        }
+        let input_bytes = std::fs::read(input_path)?;
+        let input_content = if self.config.processing.lossy_utf8 {
+            encoding::decode_lossy(&input_bytes, &self.config.processing.input_encoding)?
+        } else {
+            encoding::decode(&input_bytes, &self.config.processing.input_encoding)?
+        };
+        let mut output =
+            PartialOutput::create(output_path, &self.config.processing.output_encoding)?;
+        let result = match self.write_phantomed(&input_content, input_path, &mut output) {
+            Ok(result) => result,
+            Err(e) => return Err(output.abandon(e)),
+        };
+        output.finish()?;
+
+        if let Some(report_path) = &report_path {
+            let report_json = serde_json::to_string_pretty(&self.get_trace_report())?;
+            self.write_output(report_path, &report_json)?;
+        }
+
+        // Create trace map if requested
+        if self.config.output.create_trace_map {
+            let trace_map_path = format!("{}.tracemap", output_path);
+            self.create_trace_map(&result, &trace_map_path)?;
+        }
+
+        Ok(result)
+    }
+
+    // Phantom `input` into `output` in the configured format. Text and the line-record
+    // formats are written as they go; the other formats are single documents.
+    fn write_phantomed(
+        &mut self,
+        input: &str,
+        input_path: &str,
+        output: &mut PartialOutput,
+    ) -> Result<ProcessingResult, PhantomError> {
+        let result = match self.config.output.format {
+            OutputFormat::SplunkHec => {
+                self.phantom_line_records(input, output, false, |processor, line, line_result| {
+                    integrations::splunk_hec_event(
+                        &processor.config.preprocessing.splunk_integration,
+                        input_path,
+                        line,
+                        line_result,
+                    )
+                })?
+            }
+            OutputFormat::Elk => {
+                self.phantom_line_records(input, output, false, |processor, line, line_result| {
+                    integrations::elk_document(
+                        &processor.config.preprocessing.elk_integration,
+                        line,
+                        line_result,
+                    )
+                })?
+            }
+            OutputFormat::JsonLines | OutputFormat::JsonArray => {
+                let as_array = matches!(self.config.output.format, OutputFormat::JsonArray);
+                let mut line_number = 0;
+                self.phantom_line_records(input, output, as_array, |processor, _, line_result| {
+                    line_number += 1;
+                    let events = processor.logged_events(line_result);
+                    integrations::json_line_record(line_number, line_result, events.as_deref())
+                })?
+            }
+            OutputFormat::Sarif => {
+                // Line by line, so each event's line number is known
+                let lines: Vec<(&str, ProcessingResult)> = input
+                    .lines()
+                    .map(|line| (line, self.phantom_text(line)))
+                    .collect();
+                let borrowed: Vec<(&str, &ProcessingResult)> =
+                    lines.iter().map(|(line, result)| (*line, result)).collect();
+                let sarif = integrations::sarif_log(input_path, &borrowed);
+                output.write(&serde_json::to_string_pretty(&sarif)?)?;
+                ProcessingResult::combine(lines.into_iter().map(|(_, result)| result).collect())
+            }
+            _ => self.phantom_text(input),
+        };
 
-        // Write output based on format
         match self.config.output.format {
             OutputFormat::Text => {
-                std::fs::write(output_path, &result.phantomed_text)?;
+                for line in result.phantomed_text.split_inclusive('\n') {
+                    output.write(line)?;
+                }
             }
             OutputFormat::Json => {
                 let json_output = serde_json::to_string_pretty(&JsonOutput {
                     phantomed_text: result.phantomed_text.clone(),
-                    events: if self.config.output.log_phantom_events {
-                        Some(result.phantom_events.clone())
-                    } else {
-                        None
-                    },
+                    events: self.logged_events(&result),
                     trace_report: if self.config.output.include_trace_report {
                         Some(self.get_trace_report())
                     } else {
                         None
                     },
                 })?;
-                std::fs::write(output_path, json_output)?;
+                output.write(&json_output)?;
             }
             OutputFormat::Csv => {
-                let mut csv_content = String::new();
-                csv_content.push_str(
-                    "rule_name,severity,original_value,phantom_value,start_pos,end_pos,trace_id\n",
-                );
-                for event in &result.phantom_events {
-                    csv_content.push_str(&format!(
-                        "{},{:?},{},{},{},{},{}\n",
-                        event.rule_name,
-                        event.severity,
-                        event.original_value,
-                        event.phantom_value,
-                        event.position.0,
-                        event.position.1,
-                        event.trace_id
-                    ));
-                }
-                std::fs::write(output_path, csv_content)?;
+                output.write(&self.events_csv(&self.logged_sample(&result.phantom_events))?)?;
             }
+            OutputFormat::SplunkHec
+            | OutputFormat::Elk
+            | OutputFormat::JsonLines
+            | OutputFormat::JsonArray
+            | OutputFormat::Sarif => {}
             OutputFormat::TraceReport => {
                 let report = self.get_trace_report();
-                let report_json = serde_json::to_string_pretty(&report)?;
-                std::fs::write(output_path, report_json)?;
+                output.write(&serde_json::to_string_pretty(&report)?)?;
             }
         }
+        Ok(result)
+    }
 
-        // Create trace map if requested
-        if self.config.output.create_trace_map {
-            let trace_map_path = format!("{}.tracemap", output_path);
-            self.create_trace_map(&result, &trace_map_path)?;
+    // One row per event; values are quoted as needed, so commas, quotes and newlines in
+    // matched text can't break the columns
+    fn events_csv(&self, events: &[PhantomEvent]) -> Result<String, PhantomError> {
+        let output = &self.config.output;
+        let omit_original = output.csv_omit_original
+            || output.hash_original_values && output.original_hash_salt.is_none();
+        let events = self.emitted_events(events);
+        let mut writer = csv::Writer::from_writer(Vec::new());
+
+        let mut header = vec!["rule_name", "severity"];
+        if !omit_original {
+            header.push("original_value");
         }
+        header.extend(["phantom_value", "start_pos", "end_pos", "trace_id"]);
+        writer.write_record(&header)?;
 
-        Ok(result)
+        for event in &events {
+            let mut row = vec![event.rule_name.clone(), format!("{:?}", event.severity)];
+            if !omit_original {
+                row.push(event.original_value.clone());
+            }
+            row.extend([
+                event.phantom_value.clone(),
+                event.position.0.to_string(),
+                event.position.1.to_string(),
+                event.trace_id.clone(),
+            ]);
+            writer.write_record(&row)?;
+        }
+
+        let bytes = writer
+            .into_inner()
+            .map_err(|e| PhantomError::Io(e.into_error()))?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    // Events as written to output, with originals hashed or dropped per
+    // `output.hash_original_values`
+    fn emitted_events(&self, events: &[PhantomEvent]) -> Vec<PhantomEvent> {
+        let mut events = events.to_vec();
+        if self.config.output.hash_original_values {
+            for event in &mut events {
+                event.original_value = match &self.config.output.original_hash_salt {
+                    Some(salt) => {
+                        format!("sha256:{}", audit::salted_hash(salt, &event.original_value))
+                    }
+                    None => String::new(),
+                };
+            }
+        }
+        events
+    }
+
+    // A result's events for formats that list them, if `log_phantom_events` is set
+    fn logged_events(&self, result: &ProcessingResult) -> Option<Vec<PhantomEvent>> {
+        self.config
+            .output
+            .log_phantom_events
+            .then(|| self.emitted_events(&self.logged_sample(&result.phantom_events)))
+    }
+
+    // At most `max_logged_events` of `events`, sampled as the reservoir would have
+    fn logged_sample(&self, events: &[PhantomEvent]) -> Vec<PhantomEvent> {
+        match self.config.output.max_logged_events {
+            Some(cap) if events.len() > cap => {
+                let mut sample = EventReservoir {
+                    kept: Vec::with_capacity(cap),
+                    cap: Some(cap),
+                    seen: 0,
+                    by_rule: BTreeMap::new(),
+                    audit_log: None,
+                    event_buffer: None,
+                    rng_state: RESERVOIR_SEED,
+                };
+                events.iter().cloned().for_each(|event| sample.keep(event));
+                sample.kept
+            }
+            _ => events.to_vec(),
+        }
+    }
+
+    // `path` kept inside `output.output_root` when that is set
+    fn confine_output(&self, path: &str) -> Result<String, PhantomError> {
+        match &self.config.output.output_root {
+            Some(root) => Ok(trace_map::confine_path(Path::new(root), path)?
+                .to_string_lossy()
+                .into_owned()),
+            None => Ok(path.to_string()),
+        }
+    }
+
+    fn write_output(&self, output_path: &str, content: &str) -> Result<(), PhantomError> {
+        let bytes = encoding::encode(content, &self.config.processing.output_encoding)?;
+        trace_map::write_atomically(Path::new(output_path), &bytes)
+    }
+
+    // Process each line on its own so line-oriented formats can emit one record per line,
+    // or with `as_array` one element of a JSON array per line
+    fn phantom_line_records<F>(
+        &mut self,
+        input: &str,
+        output: &mut PartialOutput,
+        as_array: bool,
+        mut build_record: F,
+    ) -> Result<ProcessingResult, PhantomError>
+    where
+        F: FnMut(&Self, &str, &ProcessingResult) -> serde_json::Value,
+    {
+        let mut line_results = Vec::new();
+
+        for line in input.lines() {
+            let line_result = self.phantom_text(line);
+            let record = serde_json::to_string(&build_record(self, line, &line_result))?;
+            if as_array {
+                let separator = if line_results.is_empty() {
+                    "[\n"
+                } else {
+                    ",\n"
+                };
+                output.write(&format!("{}{}", separator, record))?;
+            } else {
+                output.write(&format!("{}\n", record))?;
+            }
+            line_results.push(line_result);
+        }
+        if as_array {
+            output.write(if line_results.is_empty() {
+                "[]\n"
+            } else {
+                "\n]\n"
+            })?;
+        }
+
+        Ok(ProcessingResult::combine(line_results))
     }
 
     fn create_trace_map(
         &self,
         result: &ProcessingResult,
         map_path: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), PhantomError> {
+        // Counts cover every event found, not just those kept in `phantom_events`
         let trace_map = TraceMap {
-            total_events: result.phantom_events.len(),
+            total_events: result.total_events,
             events_by_severity: {
-                let mut map = std::collections::HashMap::new();
-                for event in &result.phantom_events {
-                    *map.entry(format!("{:?}", event.severity)).or_insert(0) += 1;
-                }
-                map
-            },
-            events_by_rule: {
-                let mut map = std::collections::HashMap::new();
-                for event in &result.phantom_events {
-                    *map.entry(event.rule_name.clone()).or_insert(0) += 1;
+                let mut map = BTreeMap::new();
+                for (severity, events) in result.events_by_rule.values() {
+                    *map.entry(format!("{:?}", severity)).or_insert(0) += *events as u32;
                 }
                 map
             },
+            events_by_rule: result
+                .events_by_rule
+                .iter()
+                .map(|(rule_name, (_, events))| (rule_name.clone(), *events as u32))
+                .collect(),
             phantom_coverage: if result.lines_processed > 0 {
                 (result.lines_phantomed as f64 / result.lines_processed as f64) * 100.0
             } else {
                 0.0
             },
+            reversal: if self.config.output.reversible_trace_map {
+                let key = self.trace_map_key.as_ref().ok_or_else(|| {
+                    PhantomError::TraceMap(
+                        "reversible_trace_map is enabled but no trace map key was set".into(),
+                    )
+                })?;
+                Some(trace_map::seal_reversal(&result.phantom_events, key)?)
+            } else {
+                None
+            },
         };
 
         let trace_map_json = serde_json::to_string_pretty(&trace_map)?;
-        std::fs::write(map_path, trace_map_json)?;
-        Ok(())
+        trace_map::write_atomically(Path::new(map_path), trace_map_json.as_bytes())
+    }
+
+    /// Decrypt the reversal table from a trace map written with `reversible_trace_map`
+    pub fn load_trace_map(
+        map_path: &str,
+        key: &TraceMapKey,
+    ) -> Result<ReversalTable, PhantomError> {
+        let content = std::fs::read_to_string(map_path)?;
+        let trace_map: SealedTraceMap = serde_json::from_str(&content)?;
+        let sealed = trace_map.reversal.ok_or_else(|| {
+            PhantomError::TraceMap(format!("Trace map '{}' has no reversal data", map_path))
+        })?;
+        trace_map::open_reversal(&sealed, key)
+    }
+
+    /// Per-rule matches and would-be phantom values for `text`; nothing is recorded
+    pub fn explain(&self, text: &str) -> Vec<RuleMatchExplanation> {
+        self.tracer.explain(text)
+    }
+
+    /// The events phantoming `text` would produce; nothing is recorded
+    pub fn find_events(&self, text: &str) -> Vec<PhantomEvent> {
+        self.tracer.find_events(text)
+    }
+
+    /// Whether any rule would fire on `text`, for routing without obfuscating.
+    /// Much cheaper than `phantom_text`, especially on clean text. Rules run over the
+    /// text as-is, without JSON, CSV, logfmt, syslog or URL parsing.
+    /// Like `explain`, finds nothing until a `new_lazy` processor's rules are compiled.
+    pub fn contains_sensitive(&self, text: &str) -> bool {
+        self.tracer.contains_match(text)
     }
 
     pub fn get_trace_report(&self) -> TraceReport {
         self.tracer.get_trace_report()
     }
 
+    /// The buffer behind `monitoring.event_buffer_path`, shared with every session
+    pub fn event_buffer(&self) -> Option<&EventBuffer> {
+        self.event_buffer.as_ref()
+    }
+
+    // How reader-based callers write one processed line, without the trailing newline
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn stream_record(
+        &self,
+        line_number: u64,
+        result: &ProcessingResult,
+    ) -> Result<String, PhantomError> {
+        Ok(match self.config.output.format {
+            OutputFormat::JsonLines | OutputFormat::JsonArray => {
+                let events = self.logged_events(result);
+                serde_json::to_string(&integrations::json_line_record(
+                    line_number,
+                    result,
+                    events.as_deref(),
+                ))?
+            }
+            _ => result.phantomed_text.clone(),
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn streams_json_array(&self) -> bool {
+        matches!(self.config.output.format, OutputFormat::JsonArray)
+    }
+
+    // Line terminators stripped by reader-based callers still count as input
+    pub(crate) fn record_extra_bytes(&mut self, bytes: usize) {
+        self.add_stats(&ProcessingStats {
+            bytes_processed: bytes as u64,
+            ..ProcessingStats::default()
+        });
+    }
+
+    // How reader-based callers turn a raw line into text
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn decode_line<'a>(
+        &self,
+        bytes: &'a [u8],
+    ) -> std::io::Result<std::borrow::Cow<'a, str>> {
+        encoding::utf8_line(bytes, self.config.processing.lossy_utf8)
+    }
+
     pub fn get_processing_stats(&self) -> ProcessingStatsOutput {
+        let stats = &self.processing_stats;
+        let seconds = stats.processing_time.as_secs_f64();
+        let per_second = |count: f64| if seconds > 0.0 { count / seconds } else { 0.0 };
+
         ProcessingStatsOutput {
-            lines_processed: self.processing_stats.lines_processed,
-            lines_phantomed: self.processing_stats.lines_phantomed,
-            total_phantom_events: self.processing_stats.total_phantom_events,
-            processing_time_ms: self.processing_stats.processing_time.as_millis() as u64,
+            lines_processed: stats.lines_processed,
+            lines_phantomed: stats.lines_phantomed,
+            total_phantom_events: stats.total_phantom_events,
+            bytes_processed: stats.bytes_processed,
+            processing_time_ms: stats.processing_time.as_millis() as u64,
+            lines_per_second: per_second(stats.lines_processed as f64),
+            mb_per_second: per_second(stats.bytes_processed as f64 / (1024.0 * 1024.0)),
+            events_by_rule: stats
+                .events_by_rule
+                .iter()
+                .map(|(rule_name, (severity, events))| RuleEventCount {
+                    rule_name: rule_name.clone(),
+                    severity: severity.clone(),
+                    events: *events,
+                })
+                .collect(),
+            processing_time_histogram: stats.processing_time_histogram.clone(),
             trace_report: self.get_trace_report(),
         }
     }
@@ -182,15 +1229,231 @@ impl PhantomTraceProcessor {
         self.processing_stats = ProcessingStats::default();
         self.tracer.reset_traces();
     }
+
+    /// Zero one rule's trace stats and its `events_by_rule` count; other rules' stats and
+    /// the token map are kept. Returns whether the rule exists.
+    pub fn reset_rule_stats(&mut self, rule_name: &str) -> bool {
+        self.processing_stats.events_by_rule.remove(rule_name);
+        self.tracer.reset_rule_stats(rule_name)
+    }
+
+    /// Reset stats and tokens for the next file, first folding the finished file
+    /// into the grand total when `keep_grand_total` is set
+    pub fn start_next_file(&mut self) {
+        if let Some(grand_total) = &mut self.grand_total {
+            grand_total.merge(&self.processing_stats);
+        }
+        self.reset_stats();
+    }
+
+    /// Stats across every file so far, including the current one.
+    /// `None` unless `processing.keep_grand_total` is set.
+    pub fn grand_total_stats(&self) -> Option<ProcessingStats> {
+        self.grand_total.as_ref().map(|grand_total| {
+            let mut total = grand_total.clone();
+            total.merge(&self.processing_stats);
+            total
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct ProcessingResult {
     pub phantomed_text: String,
     pub phantom_events: Vec<PhantomEvent>,
+    pub total_events: usize, // Events found; more than `phantom_events` holds once `max_logged_events` samples
+    pub events_by_rule: BTreeMap<String, (TraceSeverity, u64)>, // Every event found, by rule
     pub lines_processed: usize,
     pub lines_phantomed: usize,
     pub processing_time: std::time::Duration,
+    pub truncated: bool, // A rule stopped early at `max_matches_per_line`
+}
+
+impl ProcessingResult {
+    // Fold per-line results back into one result for the whole input
+    fn combine(results: Vec<ProcessingResult>) -> Self {
+        let mut phantomed_lines = Vec::with_capacity(results.len());
+        let mut combined = ProcessingResult {
+            phantomed_text: String::new(),
+            phantom_events: Vec::new(),
+            total_events: 0,
+            events_by_rule: BTreeMap::new(),
+            lines_processed: 0,
+            lines_phantomed: 0,
+            processing_time: std::time::Duration::ZERO,
+            truncated: false,
+        };
+
+        for result in results {
+            phantomed_lines.push(result.phantomed_text);
+            combined.phantom_events.extend(result.phantom_events);
+            combined.total_events += result.total_events;
+            for (rule_name, (severity, events)) in result.events_by_rule {
+                combined
+                    .events_by_rule
+                    .entry(rule_name)
+                    .or_insert((severity, 0))
+                    .1 += events;
+            }
+            combined.lines_processed += result.lines_processed;
+            combined.lines_phantomed += result.lines_phantomed;
+            combined.truncated |= result.truncated;
+            combined.processing_time += result.processing_time;
+        }
+        combined.phantomed_text = phantomed_lines.join("\n");
+        combined
+    }
+}
+
+/// Ends the output of a `phantom_file` call that failed part way, followed by the error.
+/// That output is left at the output path plus `PARTIAL_OUTPUT_SUFFIX`.
+pub const PARTIAL_OUTPUT_MARKER: &str = "[PHANTOMTRACE_ERROR]";
+
+/// Appended to the output path to name where a failed `phantom_file` leaves its
+/// partial output; the output path itself keeps whatever it held before
+pub const PARTIAL_OUTPUT_SUFFIX: &str = ".partial";
+
+// `phantom_file` output, written as it's produced to a temporary file beside the output
+// path and renamed over it on success, so readers never see half a file. A failure late
+// in a large file moves everything before it to `PARTIAL_OUTPUT_SUFFIX`, followed by
+// `PARTIAL_OUTPUT_MARKER` and the error.
+struct PartialOutput {
+    file: std::io::BufWriter<std::fs::File>,
+    encoding: String,
+    temp_path: std::path::PathBuf,
+    path: std::path::PathBuf,
+}
+
+impl PartialOutput {
+    fn create(path: &str, encoding: &str) -> Result<Self, PhantomError> {
+        let path = std::path::PathBuf::from(path);
+        let temp_path = trace_map::temp_path_for(&path)?;
+        Ok(Self {
+            file: std::io::BufWriter::new(std::fs::File::create(&temp_path)?),
+            encoding: encoding.to_string(),
+            temp_path,
+            path,
+        })
+    }
+
+    fn write(&mut self, text: &str) -> Result<(), PhantomError> {
+        let bytes = encoding::encode(text, &self.encoding)?;
+        self.file.write_all(&bytes)?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), PhantomError> {
+        let done = self
+            .file
+            .flush()
+            .and_then(|_| std::fs::rename(&self.temp_path, &self.path));
+        if let Err(e) = done {
+            let _ = std::fs::remove_file(&self.temp_path);
+            return Err(e.into());
+        }
+        Ok(())
+    }
+
+    // Mark the output as incomplete and hand back the error that stopped it
+    fn abandon(mut self, error: PhantomError) -> PhantomError {
+        let marker = format!("\n{} {}\n", PARTIAL_OUTPUT_MARKER, error);
+        // The marker must land even if the message can't be encoded
+        let bytes =
+            encoding::encode(&marker, &self.encoding).unwrap_or_else(|_| marker.into_bytes());
+        if let Err(e) = self.file.write_all(&bytes).and_then(|_| self.file.flush()) {
+            eprintln!("Could not mark partial output: {}", e);
+        }
+        let mut partial_path = self.path.into_os_string();
+        partial_path.push(PARTIAL_OUTPUT_SUFFIX);
+        if let Err(e) = std::fs::rename(&self.temp_path, &partial_path) {
+            eprintln!("Could not keep partial output: {}", e);
+            let _ = std::fs::remove_file(&self.temp_path);
+        }
+        error
+    }
+}
+
+// Output of a single processing pass, before stats are folded in
+struct PhantomPass {
+    text: String,
+    events: EventReservoir,
+    lines_processed: usize,
+    lines_phantomed: usize,
+}
+
+// `output.event_line_columns` for one line's events. Positions inside a JSON field
+// value aren't offsets into the line, so those events get no column.
+fn locate_events(events: &mut [PhantomEvent], line: &str, line_number: usize) {
+    for event in events {
+        event.line_number = Some(line_number);
+        if event.field_path.is_none() {
+            event.column = line
+                .get(..event.position.0)
+                .map(|before| before.chars().count() + 1);
+        }
+    }
+}
+
+// Fixed, so the same input always keeps the same sample
+const RESERVOIR_SEED: u64 = 0x853c_49e6_748f_ea9b;
+
+// Events gathered by one pass. Every event is counted and audited as it arrives; with a
+// cap (`max_logged_events`, when only logged output needs the events) just a uniform
+// sample of that many is kept (reservoir sampling, Algorithm R), so memory stays flat
+// however many matches a file has.
+struct EventReservoir {
+    kept: Vec<PhantomEvent>,
+    cap: Option<usize>,
+    seen: u64,
+    by_rule: BTreeMap<String, (TraceSeverity, u64)>,
+    audit_log: Option<AuditLog>,
+    event_buffer: Option<EventBuffer>,
+    rng_state: u64,
+}
+
+impl EventReservoir {
+    fn extend(&mut self, events: Vec<PhantomEvent>) {
+        if let Some(audit_log) = &self.audit_log {
+            if let Err(e) = audit_log.record(&events) {
+                eprintln!("Audit log write failed: {}", e);
+            }
+        }
+        if let Some(event_buffer) = &self.event_buffer {
+            if let Err(e) = event_buffer.push(&events) {
+                eprintln!("Event buffer flush failed: {}", e);
+            }
+        }
+        for event in events {
+            self.by_rule
+                .entry(event.rule_name.clone())
+                .or_insert_with(|| (event.severity.clone(), 0))
+                .1 += 1;
+            self.keep(event);
+        }
+    }
+
+    fn keep(&mut self, event: PhantomEvent) {
+        self.seen += 1;
+        match self.cap {
+            Some(cap) if self.kept.len() >= cap => {
+                // The n-th event replaces a kept one with probability cap / n
+                let slot = (self.next_random() % self.seen) as usize;
+                if slot < cap {
+                    self.kept[slot] = event;
+                }
+            }
+            _ => self.kept.push(event),
+        }
+    }
+
+    // SplitMix64
+    fn next_random(&mut self) -> u64 {
+        self.rng_state = self.rng_state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng_state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -205,14 +1468,34 @@ pub struct ProcessingStatsOutput {
     pub lines_processed: u64,
     pub lines_phantomed: u64,
     pub total_phantom_events: u64,
+    pub bytes_processed: u64,
     pub processing_time_ms: u64,
+    pub lines_per_second: f64,
+    pub mb_per_second: f64,
+    pub events_by_rule: Vec<RuleEventCount>,
+    pub processing_time_histogram: ProcessingTimeHistogram,
     pub trace_report: TraceReport,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleEventCount {
+    pub rule_name: String,
+    pub severity: TraceSeverity,
+    pub events: u64,
+}
+
 #[derive(Debug, Serialize)]
 struct TraceMap {
     total_events: usize,
-    events_by_severity: std::collections::HashMap<String, u32>,
-    events_by_rule: std::collections::HashMap<String, u32>,
+    events_by_severity: BTreeMap<String, u32>,
+    events_by_rule: BTreeMap<String, u32>,
     phantom_coverage: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reversal: Option<EncryptedReversal>,
+}
+
+// Only the sealed section is needed when reading a trace map back
+#[derive(Debug, Deserialize)]
+struct SealedTraceMap {
+    reversal: Option<EncryptedReversal>,
 }
@@ -0,0 +1,63 @@
+use crate::config::{PerformanceTuning, RateLimitPolicy};
+use std::time::{Duration, Instant};
+
+/// What a connection should do with its next line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Admission {
+    Allow,
+    Wait(Duration), // Block until then, then ask again
+    Drop,
+}
+
+/// Per-connection token bucket: `max_lines_per_second` lines per second, with bursts of
+/// up to one second's worth
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_refill: Instant,
+    policy: RateLimitPolicy,
+    dropping: bool, // Inside a run of dropped lines; warn once per run
+}
+
+impl RateLimiter {
+    /// `None` when rate limiting is off
+    pub fn from_config(tuning: &PerformanceTuning) -> Option<Self> {
+        let rate = f64::from(tuning.max_lines_per_second?.max(1));
+        Some(Self {
+            rate,
+            tokens: rate,
+            last_refill: Instant::now(),
+            policy: tuning.rate_limit_policy.clone(),
+            dropping: false,
+        })
+    }
+
+    pub fn check(&mut self, peer: &str) -> Admission {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            self.dropping = false;
+            return Admission::Allow;
+        }
+        match self.policy {
+            RateLimitPolicy::Block => {
+                Admission::Wait(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+            }
+            RateLimitPolicy::Drop => {
+                if !self.dropping {
+                    eprintln!(
+                        "Client {} exceeded {} lines/s; dropping lines",
+                        peer, self.rate
+                    );
+                    self.dropping = true;
+                }
+                Admission::Drop
+            }
+        }
+    }
+}
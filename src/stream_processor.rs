@@ -1,54 +1,165 @@
 use crate::config::PhantomTraceConfig;
+use crate::error::PhantomError;
 use crate::processor::PhantomTraceProcessor;
-use std::io::{BufRead, BufReader, Write};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
 use std::net::{TcpListener, TcpStream}; // Added TcpStream import
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
+// How long to back off when the writer reports it can't take more yet
+const WRITE_BACKOFF: Duration = Duration::from_millis(5);
+
 #[derive(Debug)]
 pub struct StreamProcessor {
     processor: PhantomTraceProcessor,
     buffer_size: usize,
     flush_interval: Duration,
+    // Rendered output the writer hasn't accepted yet. Kept across a failed write so the
+    // next `process_reader` call resumes exactly where the writer stopped.
+    pending: Vec<u8>,
+    json_array_open: bool, // `OutputFormat::JsonArray`: `[` written, `]` not yet
+    shutdown: Option<Arc<AtomicBool>>,
 }
 
 impl StreamProcessor {
-    pub fn new(config: PhantomTraceConfig) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(config: PhantomTraceConfig) -> Result<Self, PhantomError> {
         let processor = PhantomTraceProcessor::new(config.clone())?;
         Ok(Self {
             processor,
             buffer_size: config.processing.batch_size,
             flush_interval: Duration::from_millis(100),
+            pending: Vec::new(),
+            json_array_open: false,
+            shutdown: None,
         })
     }
 
-    pub fn process_stream(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    /// Stop `process_reader` once `shutdown` is set: checked before each line is read,
+    /// the lines already buffered are written and the output finished as at end of input
+    pub fn set_shutdown(&mut self, shutdown: Arc<AtomicBool>) {
+        self.shutdown = Some(shutdown);
+    }
+
+    /// Output already processed but not yet accepted by the writer (after a write error)
+    pub fn pending_output(&self) -> &[u8] {
+        &self.pending
+    }
+
+    pub fn processor(&self) -> &PhantomTraceProcessor {
+        &self.processor
+    }
+
+    pub fn processor_mut(&mut self) -> &mut PhantomTraceProcessor {
+        &mut self.processor
+    }
+
+    pub fn process_stream(&mut self) -> Result<(), PhantomError> {
         let stdin = std::io::stdin();
         let stdout = std::io::stdout();
-        let mut stdout_lock = stdout.lock();
+        self.process_reader(stdin.lock(), stdout.lock())
+    }
+
+    // Line-by-line processing over any reader/writer pair. At most `batch_size` lines
+    // are in flight: no more input is read until the writer has taken the previous
+    // batch, so a slow writer slows the reader instead of growing memory. A write error
+    // is returned at once; the unwritten output stays in `pending_output` and is
+    // written first on the next call.
+    pub fn process_reader<R: BufRead, W: Write>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+    ) -> Result<(), PhantomError> {
+        self.write_pending(&mut writer)?;
         let mut buffer = Vec::new();
+        let mut raw_bytes = Vec::new();
+        let mut line_number = 0;
 
-        for line in stdin.lock().lines() {
-            let line = line?;
-            buffer.push(line);
+        loop {
+            raw_bytes.clear();
+            let stopping = self
+                .shutdown
+                .as_ref()
+                .is_some_and(|shutdown| shutdown.load(Ordering::Relaxed));
+            let at_end = stopping || reader.read_until(b'\n', &mut raw_bytes)? == 0;
+            if !at_end {
+                let raw_line = self.processor.decode_line(&raw_bytes)?;
+                let line = raw_line.strip_suffix('\n').unwrap_or(&raw_line);
+                let line = line.strip_suffix('\r').unwrap_or(line);
+                self.processor
+                    .record_extra_bytes(raw_line.len() - line.len());
+                buffer.push(line.to_string());
+            }
 
-            if buffer.len() >= self.buffer_size {
-                // Use buffer_size here
+            if buffer.len() >= self.buffer_size.max(1) || at_end && !buffer.is_empty() {
                 for buffered_line in buffer.drain(..) {
+                    line_number += 1;
                     let result = self.processor.phantom_text(&buffered_line);
-                    writeln!(stdout_lock, "{}", result.phantomed_text)?;
+                    let record = self.processor.stream_record(line_number, &result)?;
+                    if self.processor.streams_json_array() {
+                        let separator = if self.json_array_open { ",\n" } else { "[\n" };
+                        self.pending.extend_from_slice(separator.as_bytes());
+                        self.json_array_open = true;
+                        self.pending.extend_from_slice(record.as_bytes());
+                    } else {
+                        self.pending.extend_from_slice(record.as_bytes());
+                        self.pending.push(b'\n');
+                    }
                 }
-                stdout_lock.flush()?;
+                self.write_pending(&mut writer)?;
+            }
+            if at_end {
+                return self.finish(writer);
             }
         }
+    }
 
-        // Process remaining items in buffer
-        for buffered_line in buffer {
-            let result = self.processor.phantom_text(&buffered_line);
-            writeln!(stdout_lock, "{}", result.phantomed_text)?;
+    /// End the output of a stream. With `OutputFormat::JsonArray` this closes the array,
+    /// so call it when stopping a stream early (on shutdown, or after a `process_reader`
+    /// error) to leave valid JSON behind; `process_reader` calls it at end of input.
+    pub fn finish<W: Write>(&mut self, mut writer: W) -> Result<(), PhantomError> {
+        if self.processor.streams_json_array() {
+            let close = if self.json_array_open {
+                "\n]\n"
+            } else {
+                "[]\n"
+            };
+            self.pending.extend_from_slice(close.as_bytes());
+            self.json_array_open = false;
+        }
+        self.write_pending(&mut writer)
+    }
+
+    // Hand `pending` to the writer, waiting out `WouldBlock` and retrying `Interrupted`.
+    // On any other error the bytes the writer did accept are dropped from `pending`
+    // and the rest are kept.
+    fn write_pending<W: Write>(&mut self, writer: &mut W) -> Result<(), PhantomError> {
+        let mut written = 0;
+        while written < self.pending.len() {
+            match writer.write(&self.pending[written..]) {
+                Ok(0) => {
+                    self.pending.drain(..written);
+                    return Err(std::io::Error::from(ErrorKind::WriteZero).into());
+                }
+                Ok(n) => written += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(WRITE_BACKOFF),
+                Err(e) => {
+                    self.pending.drain(..written);
+                    return Err(e.into());
+                }
+            }
+        }
+        self.pending.clear();
+        loop {
+            match writer.flush() {
+                Ok(()) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(WRITE_BACKOFF),
+                Err(e) => return Err(e.into()),
+            }
         }
-        stdout_lock.flush()?;
-        Ok(())
     }
 
     // File monitoring for log file preprocessing (fixed borrowing issue)
@@ -56,7 +167,7 @@ impl StreamProcessor {
         &mut self,
         input_path: &str,
         output_path: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
+    ) -> Result<(), PhantomError> {
         use std::fs::File;
         use std::io::{Seek, SeekFrom};
 
@@ -67,14 +178,20 @@ impl StreamProcessor {
             let mut file = File::open(input_path)?; // Reopen file each iteration
             file.seek(SeekFrom::Start(last_pos))?;
 
-            let reader = BufReader::new(file);
+            let mut reader = BufReader::new(file);
             let mut new_pos = last_pos;
+            let mut raw_bytes = Vec::new();
 
-            for line in reader.lines() {
-                let line = line?;
-                let result = self.processor.phantom_text(&line);
+            loop {
+                raw_bytes.clear();
+                if reader.read_until(b'\n', &mut raw_bytes)? == 0 {
+                    break;
+                }
+                let line = self.processor.decode_line(&raw_bytes)?;
+                let line = line.strip_suffix('\n').unwrap_or(&line);
+                let result = self.processor.phantom_text(line);
                 writeln!(output, "{}", result.phantomed_text)?;
-                new_pos += line.len() as u64 + 1; // +1 for newline
+                new_pos += raw_bytes.len() as u64;
             }
 
             last_pos = new_pos;
@@ -84,7 +201,7 @@ impl StreamProcessor {
     }
 
     // TCP server mode for network log ingestion
-    pub fn serve_tcp(&mut self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn serve_tcp(&mut self, port: u16) -> Result<(), PhantomError> {
         let listener = TcpListener::bind(format!("0.0.0.0:{}", port))?;
         println!("👻 PhantomTrace TCP server listening on port {}", port);
 
@@ -113,7 +230,7 @@ impl StreamProcessor {
 fn handle_tcp_client(
     stream: TcpStream, // Removed mut since we'll clone it
     processor: &mut PhantomTraceProcessor,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), PhantomError> {
     let mut write_stream = stream.try_clone()?; // Clone for writing
     let reader = BufReader::new(stream); // Use original for reading
 
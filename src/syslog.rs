@@ -0,0 +1,23 @@
+use regex::Regex;
+use std::sync::LazyLock;
+
+// <PRI>VERSION TIMESTAMP HOSTNAME APP-NAME PROCID MSGID, followed by SD and MSG
+static RFC5424_HEADER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^<\d{1,3}>\d{1,2} \S+ \S+ \S+ \S+ \S+ ").unwrap());
+
+// [<PRI>]Mmm dd hh:mm:ss HOSTNAME [TAG[pid]:] MSG (PRI is often stripped in log files)
+static RFC3164_HEADER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:<\d{1,3}>)?[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2} \S+ (?:[^:\s\[]+(?:\[\d+\])?: )?",
+    )
+    .unwrap()
+});
+
+/// Split a syslog line into its header and the part rules should see
+/// (structured data and message). Returns `None` when the line isn't syslog.
+pub fn split_header(line: &str) -> Option<(&str, &str)> {
+    RFC5424_HEADER
+        .find(line)
+        .or_else(|| RFC3164_HEADER.find(line))
+        .map(|header| line.split_at(header.end()))
+}
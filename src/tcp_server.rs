@@ -0,0 +1,160 @@
+use crate::config::{PhantomTraceConfig, TcpFraming};
+use crate::error::PhantomError;
+use crate::processor::PhantomTraceProcessor;
+use crate::rate_limit::{Admission, RateLimiter};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long in-flight clients may keep their connection open after shutdown is signalled
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Serve the phantom protocol (line-oriented, or as `preprocessing.tcp_framing` says)
+/// with one thread per connection
+/// until `shutdown` is set. The listener is closed as soon as shutdown is
+/// observed; connected clients then get up to `grace_period` to finish.
+pub fn serve_listener(
+    config: &PhantomTraceConfig,
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+    grace_period: Duration,
+) -> Result<(), PhantomError> {
+    // Rules are compiled once; every connection gets a cheap session of this processor
+    let template = PhantomTraceProcessor::new(config.clone())?;
+    serve_processor(template, listener, shutdown, grace_period)
+}
+
+/// `serve_listener` with connections served by sessions of `template`, e.g. one
+/// given a stats sink for the health server
+pub fn serve_processor(
+    template: PhantomTraceProcessor,
+    listener: TcpListener,
+    shutdown: Arc<AtomicBool>,
+    grace_period: Duration,
+) -> Result<(), PhantomError> {
+    // Non-blocking accepts so the shutdown flag is checked between connections
+    listener.set_nonblocking(true)?;
+    let active_clients = Arc::new(AtomicUsize::new(0));
+    let config = template.config.clone();
+
+    while !shutdown.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // Accepted sockets may inherit the listener's non-blocking mode
+                if let Err(e) = stream.set_nonblocking(false) {
+                    eprintln!("Client error: {}", e);
+                    continue;
+                }
+                let processor = template.session();
+                let limiter = RateLimiter::from_config(&config.preprocessing.performance_tuning);
+                let framing = config.preprocessing.tcp_framing;
+                let active_clients = active_clients.clone();
+                active_clients.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(stream, processor, limiter, framing) {
+                        eprintln!("Client error: {}", e);
+                    }
+                    active_clients.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => eprintln!("Connection failed: {}", e),
+        }
+    }
+
+    drop(listener);
+    let deadline = Instant::now() + grace_period;
+    while active_clients.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    let remaining = active_clients.load(Ordering::SeqCst);
+    if remaining > 0 {
+        eprintln!(
+            "Grace period elapsed with {} client(s) still connected",
+            remaining
+        );
+    }
+    Ok(())
+}
+
+fn handle_client(
+    stream: TcpStream,
+    mut processor: PhantomTraceProcessor,
+    mut limiter: Option<RateLimiter>,
+    framing: TcpFraming,
+) -> Result<(), PhantomError> {
+    let peer = stream
+        .peer_addr()
+        .map_or_else(|_| "unknown".to_string(), |addr| addr.to_string());
+    let mut write_stream = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    'records: while let Some(record) = read_record(&mut reader, framing, &processor)? {
+        // Only this connection's thread waits, never the accept loop
+        while let Some(limiter) = &mut limiter {
+            match limiter.check(&peer) {
+                Admission::Allow => break,
+                Admission::Wait(delay) => thread::sleep(delay),
+                Admission::Drop => continue 'records,
+            }
+        }
+        let result = processor.phantom_text(&record);
+        match framing {
+            TcpFraming::Line => writeln!(write_stream, "{}", result.phantomed_text)?,
+            TcpFraming::LengthPrefixed => {
+                let payload = result.phantomed_text.as_bytes();
+                let len = u32::try_from(payload.len())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                write_stream.write_all(&len.to_be_bytes())?;
+                write_stream.write_all(payload)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// The next record from the client, or `None` once it has closed its side
+fn read_record(
+    reader: &mut BufReader<TcpStream>,
+    framing: TcpFraming,
+    processor: &PhantomTraceProcessor,
+) -> Result<Option<String>, PhantomError> {
+    match framing {
+        TcpFraming::Line => {
+            let mut raw_line = Vec::new();
+            if reader.read_until(b'\n', &mut raw_line)? == 0 {
+                return Ok(None);
+            }
+            let line = processor.decode_line(&raw_line)?;
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()))
+        }
+        TcpFraming::LengthPrefixed => {
+            if reader.fill_buf()?.is_empty() {
+                return Ok(None);
+            }
+            let mut len = [0; 4];
+            reader.read_exact(&mut len)?;
+            let len = u32::from_be_bytes(len) as usize;
+            let max_frame_bytes = processor.config.preprocessing.max_frame_bytes;
+            if len > max_frame_bytes {
+                let message = format!("frame of {} bytes exceeds {}", len, max_frame_bytes);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message).into());
+            }
+            // Grown as bytes arrive, so a header alone can't make us allocate `len`
+            let mut payload = Vec::new();
+            reader.by_ref().take(len as u64).read_to_end(&mut payload)?;
+            if payload.len() < len {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            Ok(Some(processor.decode_line(&payload)?.into_owned()))
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::PhantomError;
+use crate::tracer::PhantomEvent;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+const TRACE_MAP_KEY_LEN: usize = 32;
+const TRACE_MAP_ALGORITHM: &str = "AES-256-GCM";
+
+/// 256-bit key used to seal the reversal table inside a `.tracemap` file
+#[derive(Clone)]
+pub struct TraceMapKey([u8; TRACE_MAP_KEY_LEN]);
+
+impl TraceMapKey {
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PhantomError> {
+        let key: [u8; TRACE_MAP_KEY_LEN] = bytes.try_into().map_err(|_| {
+            PhantomError::TraceMap(format!(
+                "Trace map key must be {} bytes, got {}",
+                TRACE_MAP_KEY_LEN,
+                bytes.len()
+            ))
+        })?;
+        Ok(Self(key))
+    }
+
+    /// Parse a key written as 64 hex characters (surrounding whitespace ignored)
+    pub fn from_hex(hex: &str) -> Result<Self, PhantomError> {
+        Self::from_bytes(&decode_hex(hex.trim())?)
+    }
+}
+
+// Never print key material
+impl std::fmt::Debug for TraceMapKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("TraceMapKey(..)")
+    }
+}
+
+/// One original → phantom mapping recovered from an encrypted trace map
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReversalEntry {
+    pub trace_id: String,
+    pub rule_name: String,
+    pub original_value: String,
+    pub phantom_value: String,
+}
+
+/// Decrypted reversal table for undoing obfuscation of specific events
+#[derive(Debug, Clone, Default)]
+pub struct ReversalTable {
+    entries: Vec<ReversalEntry>,
+}
+
+impl ReversalTable {
+    pub fn entries(&self) -> &[ReversalEntry] {
+        &self.entries
+    }
+
+    /// Original value of the first event that produced `phantom_value`.
+    /// Non-unique methods (Mask, Vanish) map many originals to one phantom value,
+    /// so prefer `reverse_trace` when the trace ID is known.
+    pub fn reverse(&self, phantom_value: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| entry.phantom_value == phantom_value)
+            .map(|entry| entry.original_value.as_str())
+    }
+
+    pub fn reverse_trace(&self, trace_id: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| entry.trace_id == trace_id)
+            .map(|entry| entry.original_value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EncryptedReversal {
+    algorithm: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub(crate) fn seal_reversal(
+    events: &[PhantomEvent],
+    key: &TraceMapKey,
+) -> Result<EncryptedReversal, PhantomError> {
+    let entries: Vec<ReversalEntry> = events
+        .iter()
+        .map(|event| ReversalEntry {
+            trace_id: event.trace_id.clone(),
+            rule_name: event.rule_name.clone(),
+            original_value: event.original_value.clone(),
+            phantom_value: event.phantom_value.clone(),
+        })
+        .collect();
+    let plaintext = serde_json::to_vec(&entries)?;
+
+    let cipher = Aes256Gcm::new(&key.0.into());
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_ref())
+        .map_err(|_| PhantomError::TraceMap("Failed to encrypt trace map".into()))?;
+
+    Ok(EncryptedReversal {
+        algorithm: TRACE_MAP_ALGORITHM.to_string(),
+        nonce: encode_hex(&nonce),
+        ciphertext: encode_hex(&ciphertext),
+    })
+}
+
+pub(crate) fn open_reversal(
+    sealed: &EncryptedReversal,
+    key: &TraceMapKey,
+) -> Result<ReversalTable, PhantomError> {
+    if sealed.algorithm != TRACE_MAP_ALGORITHM {
+        return Err(PhantomError::TraceMap(format!(
+            "Unsupported trace map algorithm: {}",
+            sealed.algorithm
+        )));
+    }
+
+    let nonce = decode_hex(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(PhantomError::TraceMap("Trace map nonce is corrupt".into()));
+    }
+    let ciphertext = decode_hex(&sealed.ciphertext)?;
+
+    let cipher = Aes256Gcm::new(&key.0.into());
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_ref())
+        .map_err(|_| {
+            PhantomError::TraceMap(
+                "Failed to decrypt trace map (wrong key or tampered file)".into(),
+            )
+        })?;
+
+    Ok(ReversalTable {
+        entries: serde_json::from_slice(&plaintext)?,
+    })
+}
+
+/// Resolve an untrusted relative path under `root`. Absolute paths, drive prefixes
+/// and `..` components are rejected, so the result can't escape `root`.
+pub fn confine_path(root: &Path, untrusted: &str) -> Result<PathBuf, PhantomError> {
+    let relative = Path::new(untrusted);
+    for component in relative.components() {
+        match component {
+            Component::Normal(_) | Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(PhantomError::Config(format!(
+                    "Path '{}' must not contain '..'",
+                    untrusted
+                )))
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(PhantomError::Config(format!(
+                    "Path '{}' must be relative to the output root",
+                    untrusted
+                )))
+            }
+        }
+    }
+    if relative.file_name().is_none() {
+        return Err(PhantomError::Config(format!(
+            "Path '{}' does not name a file",
+            untrusted
+        )));
+    }
+    Ok(root.join(relative))
+}
+
+/// Write `content` to a temporary file beside `path`, then rename it into place, so
+/// readers see either the previous file or the complete new one
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> Result<(), PhantomError> {
+    let temp_path = temp_path_for(path)?;
+    let written =
+        std::fs::write(&temp_path, content).and_then(|_| std::fs::rename(&temp_path, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// Hidden, per-process temporary name beside `path`; the same directory keeps the
+/// final rename on one filesystem, where it's atomic
+pub(crate) fn temp_path_for(path: &Path) -> Result<PathBuf, PhantomError> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| PhantomError::Config(format!("'{}' is not a file path", path.display())))?;
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(file_name);
+    temp_name.push(format!(".{}.tmp", std::process::id()));
+    Ok(path.with_file_name(temp_name))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, PhantomError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(PhantomError::TraceMap("Invalid hex string".into()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| PhantomError::TraceMap("Invalid hex string".into()))
+        })
+        .collect()
+}
@@ -1,12 +1,72 @@
-use regex::Regex;
-use serde::Serialize;
-use std::collections::HashMap;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use unicode_segmentation::UnicodeSegmentation;
+
+#[cfg(test)]
+thread_local! {
+    // Regexes compiled on this thread, so tests can prove compiled rules are reused
+    pub(crate) static RULE_COMPILATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+// Issued Mirror/Tokenize hashes: (scope, hash) -> fingerprint of the value holding it
+type IssuedHashes = HashMap<(String, u64), u64>;
+
+type HookFn = dyn Fn(&PhantomEvent) -> Option<String> + Send + Sync;
+
+/// Per-match override: return `Some` to replace the computed phantom value
+pub type ObfuscationHook = Box<HookFn>;
 
 #[derive(Debug, Clone)]
 pub struct PhantomTracer {
-    compiled_rules: Vec<CompiledTraceRule>,
+    compiled_rules: Arc<Vec<CompiledTraceRule>>, // Severity-sorted; shared by sessions
+    // All rule patterns in `compiled_rules` order, built on the first `contains_match`.
+    // `None` if the combined set is too large to compile; rules are then tried one by one.
+    rule_set: Arc<OnceLock<Option<RegexSet>>>,
     trace_stats: HashMap<String, TraceStats>,
-    phantom_tokens: HashMap<String, String>, // For consistent tokenization
+    // Mirror/Tokenize hashes handed out per scope, with a fingerprint of the value each
+    // belongs to, so a collision between distinct values is detected without retaining them.
+    // Behind a lock so sessions can share it when `shared_tokens` is set.
+    issued_hashes: Arc<Mutex<IssuedHashes>>,
+    shared_tokens: bool,
+    mask_char: char,
+    grapheme_masking: bool,
+    min_confidence: f32,
+    allowlist: HashSet<String>,
+    allowlist_patterns: Vec<Regex>,
+    obfuscation_hook: Option<SharedHook>,
+    clock: Arc<dyn Clock>,
+    max_matches_per_line: Option<usize>,
+    truncated: bool,
+    deterministic_trace_ids: bool,
+    occurrences: HashMap<(String, u64), u64>, // (rule, value hash) -> matches seen so far
+    consistent_outputs: HashMap<(String, u64), String>, // (rule, value fingerprint) -> phantom output
+    consistent_order: VecDeque<(String, u64)>,          // `consistent_outputs` keys, oldest first
+    active_tags: Option<Vec<String>>, // If set, tagged rules run only when one of their tags is here
+    sample_matches: Option<usize>,    // Masked examples kept per rule in the stats
+    redact_keys: Vec<Regex>,
+    key_redaction: Option<Arc<CompiledTraceRule>>, // Obfuscates whole values of `redact_keys`
+    url_param_redaction: Option<Arc<CompiledTraceRule>>, // Same, for sensitive URL query values
+}
+
+// Distinct values whose `consistent` output is remembered, across all rules
+const MAX_CONSISTENT_OUTPUTS: usize = 65_536;
+
+/// Rule name on events and stats for values redacted because of their key
+pub const KEY_REDACTION_RULE: &str = "redacted_key";
+
+/// Rule name on events and stats for redacted URL query parameter values
+pub const URL_PARAM_RULE: &str = "url_query_param";
+
+// Shared so tracers stay cheaply cloneable with a hook installed
+#[derive(Clone)]
+struct SharedHook(Arc<HookFn>);
+
+impl std::fmt::Debug for SharedHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ObfuscationHook")
+    }
 }
 
 #[derive(Debug, Clone)] // Clone needed for .clone() on Vec<CompiledTraceRule>
@@ -17,41 +77,235 @@ struct CompiledTraceRule {
     preserve_chars: Option<usize>,
     replacement: Option<String>,
     severity: TraceSeverity,
+    priority: i32,
+    min_length: Option<usize>,
+    max_length: Option<usize>,
+    context_keywords: Vec<String>, // Lowercased for case-insensitive lookup
+    context_window: usize,
+    trim_chars: Option<Vec<char>>, // Set when `trim_match` is on
+    token_format: Option<String>,
+    mask_char: Option<char>,
+    obfuscate_groups: Vec<String>,
+    mirror_preserve_length: bool,
+    validator: Option<MatchValidator>,
+    consistent: bool,
+    tags: Vec<String>,
+    requires: Vec<String>,
+    declaration: usize, // Position among the configured rules
+    compile_time: std::time::Duration,
 }
 
-#[derive(Debug, Default, Clone, Serialize)]
+impl CompiledTraceRule {
+    fn compile(
+        rule: &TraceRule,
+        case_sensitive: bool,
+        unicode_boundaries: bool,
+        limits: &RegexLimits,
+    ) -> Result<Self, PhantomError> {
+        let pattern = if unicode_boundaries {
+            rule.pattern.clone()
+        } else {
+            ascii_word_boundaries(&rule.pattern)
+        };
+        let pattern = if rule.anchored {
+            format!("^(?:{})$", pattern)
+        } else {
+            pattern
+        };
+        let compile_start = crate::clock::Instant::now();
+        let regex = if case_sensitive {
+            limits.build(&rule.name, &pattern)
+        } else {
+            limits.build(&rule.name, &format!("(?i){}", pattern))
+        }?;
+        #[cfg(test)]
+        RULE_COMPILATIONS.with(|count| count.set(count.get() + 1));
+        if let Some(missing) = rule
+            .obfuscate_groups
+            .iter()
+            .find(|group| !regex.capture_names().flatten().any(|name| name == *group))
+        {
+            return Err(PhantomError::Config(format!(
+                "Rule '{}' has no capture group named '{}'",
+                rule.name, missing
+            )));
+        }
+
+        Ok(CompiledTraceRule {
+            name: rule.name.clone(),
+            regex,
+            // Rules from `effective_rules` always have one
+            method: rule.method.clone().unwrap_or(ObfuscationMethod::Phantom),
+            preserve_chars: rule.preserve_chars,
+            replacement: rule.replacement.clone(),
+            severity: rule.severity.clone(),
+            priority: rule.priority,
+            min_length: rule.min_length,
+            max_length: rule.max_length,
+            context_keywords: rule
+                .context_keywords
+                .iter()
+                .map(|keyword| keyword.to_lowercase())
+                .collect(),
+            context_window: rule.context_window,
+            trim_chars: rule.trim_match.then(|| rule.trim_chars.chars().collect()),
+            token_format: rule.token_format.clone(),
+            mask_char: rule.mask_char,
+            obfuscate_groups: rule.obfuscate_groups.clone(),
+            mirror_preserve_length: rule.mirror_preserve_length,
+            consistent: rule.consistent,
+            tags: rule.tags.clone(),
+            requires: rule.requires.clone(),
+            validator: rule.validator,
+            declaration: 0,
+            compile_time: compile_start.elapsed(),
+        })
+    }
+
+    // Split a match into (leading, core, trailing) so only the core is obfuscated
+    fn split_trimmed<'a>(&self, value: &'a str) -> (&'a str, &'a str, &'a str) {
+        let Some(trim_chars) = &self.trim_chars else {
+            return ("", value, "");
+        };
+        let is_boundary = |c: char| c.is_whitespace() || trim_chars.contains(&c);
+        let rest = value.trim_start_matches(is_boundary);
+        let core = rest.trim_end_matches(is_boundary);
+        (
+            &value[..value.len() - rest.len()],
+            core,
+            &rest[core.len()..],
+        )
+    }
+
+    // With `obfuscate_groups`, run `obfuscate` on just those groups (clipped to `matched`,
+    // which starts at `start` in the haystack) and keep the rest of the match as is
+    fn obfuscate_parts(
+        &self,
+        matched: &str,
+        start: usize,
+        caps: &regex::Captures,
+        mut obfuscate: impl FnMut(&str) -> String,
+    ) -> String {
+        if self.obfuscate_groups.is_empty() {
+            return obfuscate(matched);
+        }
+        let end = start + matched.len();
+        let mut spans: Vec<(usize, usize)> = self
+            .obfuscate_groups
+            .iter()
+            .filter_map(|name| caps.name(name))
+            .map(|group| (group.start().max(start), group.end().min(end)))
+            .filter(|(group_start, group_end)| group_start < group_end)
+            .collect();
+        spans.sort_unstable();
+
+        let mut rebuilt = String::with_capacity(matched.len());
+        let mut cursor = start;
+        for (group_start, group_end) in spans {
+            // Nested or overlapping groups: the outer (earlier) one already covered it
+            if group_start < cursor {
+                continue;
+            }
+            rebuilt.push_str(&matched[cursor - start..group_start - start]);
+            rebuilt.push_str(&obfuscate(&matched[group_start - start..group_end - start]));
+            cursor = group_end;
+        }
+        rebuilt.push_str(&matched[cursor - start..]);
+        rebuilt
+    }
+
+    // 0.7 for a bare pattern match; a validator moves it to 1.0 (pass) or 0.3 (fail),
+    // and a required context keyword (already checked to be present) adds 0.2
+    fn confidence(&self, matched: &str) -> f32 {
+        let base = match self.validator {
+            Some(validator) if validator.is_valid(matched) => 1.0,
+            Some(_) => 0.3,
+            None => 0.7,
+        };
+        if self.context_keywords.is_empty() {
+            base
+        } else {
+            f32::min(base + 0.2, 1.0)
+        }
+    }
+
+    fn accepts_length(&self, value: &str) -> bool {
+        let len = value.chars().count();
+        self.min_length.is_none_or(|min| len >= min) && self.max_length.is_none_or(|max| len <= max)
+    }
+
+    // Substitute for lookbehind: look for a keyword in the `context_window` chars before the match
+    fn has_context(&self, haystack: &str, match_start: usize) -> bool {
+        if self.context_keywords.is_empty() {
+            return true;
+        }
+        let preceding = &haystack[..match_start];
+        let window_start = match self.context_window {
+            0 => match_start,
+            window => preceding
+                .char_indices()
+                .rev()
+                .nth(window - 1)
+                .map_or(0, |(index, _)| index),
+        };
+        let window = preceding[window_start..].to_lowercase();
+        self.context_keywords
+            .iter()
+            .any(|keyword| window.contains(keyword.as_str()))
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct TraceStats {
     pub phantoms_created: u64,
     pub characters_traced: u64,
     pub severity_level: String,
+    #[serde(with = "crate::rfc3339::option")]
     pub first_trace: Option<std::time::SystemTime>,
+    #[serde(with = "crate::rfc3339::option")]
     pub last_trace: Option<std::time::SystemTime>,
+    // Distinct masked shapes of matched values (`999-99-9999`), never raw text; only
+    // collected when `output.sample_matches` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample_matches: Vec<String>,
 }
 
 impl PhantomTracer {
     // UPDATED: Accept case_sensitive as a parameter
-    pub fn new(
+    pub fn new(rules: &[TraceRule], case_sensitive: bool) -> Result<Self, PhantomError> {
+        Self::with_boundaries(rules, case_sensitive, true)
+    }
+
+    /// Like `new`, choosing Unicode (`true`) or ASCII word boundaries for `\b` and `\B`
+    pub fn with_boundaries(
+        rules: &[TraceRule],
+        case_sensitive: bool,
+        unicode_boundaries: bool,
+    ) -> Result<Self, PhantomError> {
+        Self::with_limits(
+            rules,
+            case_sensitive,
+            unicode_boundaries,
+            &RegexLimits::default(),
+        )
+    }
+
+    /// Like `with_boundaries`, compiling each pattern within `limits`
+    pub fn with_limits(
         rules: &[TraceRule],
         case_sensitive: bool,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+        unicode_boundaries: bool,
+        limits: &RegexLimits,
+    ) -> Result<Self, PhantomError> {
         let mut compiled_rules = Vec::new();
         let mut trace_stats = HashMap::new();
 
-        for rule in rules {
-            let regex = if case_sensitive {
-                Regex::new(&rule.pattern)?
-            } else {
-                Regex::new(&format!("(?i){}", rule.pattern))?
-            };
-
-            compiled_rules.push(CompiledTraceRule {
-                name: rule.name.clone(),
-                regex,
-                method: rule.method.clone(),
-                preserve_chars: rule.preserve_chars,
-                replacement: rule.replacement.clone(),
-                severity: rule.severity.clone(),
-            });
+        // Disabled rules keep their config but are never compiled or applied
+        for (declaration, rule) in rules.iter().enumerate().filter(|(_, rule)| rule.enabled) {
+            let mut compiled =
+                CompiledTraceRule::compile(rule, case_sensitive, unicode_boundaries, limits)?;
+            compiled.declaration = declaration;
+            compiled_rules.push(compiled);
 
             trace_stats.insert(
                 rule.name.clone(),
@@ -62,119 +316,790 @@ impl PhantomTracer {
             );
         }
 
+        rank_rules(&mut compiled_rules, &RuleOrder::Severity);
+
         Ok(Self {
-            compiled_rules,
+            compiled_rules: Arc::new(compiled_rules),
+            rule_set: Arc::default(),
             trace_stats,
-            phantom_tokens: HashMap::new(),
+            issued_hashes: Arc::default(),
+            shared_tokens: false,
+            mask_char: default_mask_char(),
+            grapheme_masking: false,
+            min_confidence: 0.0,
+            allowlist: HashSet::new(),
+            allowlist_patterns: Vec::new(),
+            obfuscation_hook: None,
+            clock: Arc::new(SystemClock),
+            max_matches_per_line: None,
+            truncated: false,
+            deterministic_trace_ids: false,
+            occurrences: HashMap::new(),
+            consistent_outputs: HashMap::new(),
+            consistent_order: VecDeque::new(),
+            active_tags: None,
+            sample_matches: None,
+            redact_keys: Vec::new(),
+            key_redaction: None,
+            url_param_redaction: None,
         })
     }
 
-    pub fn trace_and_phantom(&mut self, text: &str) -> (String, Vec<PhantomEvent>) {
-        let mut result = text.to_string();
-        let mut events = Vec::new();
-
-        // Process rules by severity (Critical first)
-        let mut sorted_rules = self.compiled_rules.clone();
-        sorted_rules.sort_by(|a, b| {
-            let a_priority = match a.severity {
-                TraceSeverity::Critical => 0,
-                TraceSeverity::High => 1,
-                TraceSeverity::Medium => 2,
-                TraceSeverity::Low => 3,
-            };
-            let b_priority = match b.severity {
-                TraceSeverity::Critical => 0,
-                TraceSeverity::High => 1,
-                TraceSeverity::Medium => 2,
-                TraceSeverity::Low => 3,
-            };
-            a_priority.cmp(&b_priority)
-        });
+    pub fn set_obfuscation_hook(&mut self, hook: ObfuscationHook) {
+        self.obfuscation_hook = Some(SharedHook(Arc::from(hook)));
+    }
 
-        for rule in &sorted_rules {
-            let original_result = result.clone();
-            result = rule
-                .regex
-                .replace_all(&result, |caps: &regex::Captures| {
-                    let matched = caps.get(0).map_or("", |m| m.as_str());
-                    let phantomed = self.apply_obfuscation(
-                        matched,
-                        &rule.method,
-                        rule.preserve_chars,
-                        &rule.replacement,
-                    );
-
-                    // Record the phantom event
-                    events.push(PhantomEvent {
-                        rule_name: rule.name.clone(),
-                        severity: rule.severity.clone(),
-                        original_value: matched.to_string(),
-                        phantom_value: phantomed.clone(),
-                        position: caps.get(0).map(|m| (m.start(), m.end())).unwrap_or((0, 0)),
-                        trace_id: generate_trace_id(),
-                    });
-
-                    phantomed
-                })
-                .to_string();
+    /// Replace the real clock, e.g. with a `MockClock` in tests
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // Carry a hook and clock installed on `other` over to this tracer
+    pub(crate) fn with_overrides_of(mut self, other: &PhantomTracer) -> Self {
+        self.obfuscation_hook = other.obfuscation_hook.clone();
+        self.clock = Arc::clone(&other.clock);
+        self
+    }
+
+    /// How long each compiled (enabled) rule's pattern took to compile, in rank order
+    pub fn compile_stats(&self) -> CompileStats {
+        let rules: Vec<RuleCompileTime> = self
+            .compiled_rules
+            .iter()
+            .map(|rule| RuleCompileTime {
+                rule_name: rule.name.clone(),
+                duration: rule.compile_time,
+            })
+            .collect();
+        CompileStats {
+            total: rules.iter().map(|rule| rule.duration).sum(),
+            rules,
+        }
+    }
+
+    pub fn with_mask_char(mut self, mask_char: char) -> Self {
+        self.mask_char = mask_char;
+        self
+    }
+
+    /// Preserve and mask whole grapheme clusters in the Phantom method, so a flag emoji
+    /// or an accented letter built from combining marks is never cut in half
+    pub fn with_grapheme_masking(mut self, grapheme_masking: bool) -> Self {
+        self.grapheme_masking = grapheme_masking;
+        self
+    }
+
+    /// Leave matches whose confidence is below `min_confidence` untouched
+    pub fn with_min_confidence(mut self, min_confidence: f32) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
+
+    /// Re-rank the rules; see `RuleOrder`
+    pub fn with_rule_order(mut self, order: &RuleOrder) -> Self {
+        rank_rules(
+            Arc::make_mut(&mut self.compiled_rules).as_mut_slice(),
+            order,
+        );
+        self.rule_set = Arc::default(); // Set indices follow rule order
+        self
+    }
+
+    pub fn with_max_matches_per_line(mut self, max_matches: Option<usize>) -> Self {
+        self.max_matches_per_line = max_matches;
+        self
+    }
+
+    /// Sessions created from this tracer share one token map, so even a rehashed
+    /// (collided) value gets the same token on every connection
+    pub fn with_shared_token_map(mut self, shared: bool) -> Self {
+        self.shared_tokens = shared;
+        self
+    }
+
+    fn issued_hashes(&self) -> MutexGuard<'_, IssuedHashes> {
+        self.issued_hashes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Keep up to `limit` distinct masked examples per rule in `TraceStats::sample_matches`
+    pub fn with_sample_matches(mut self, limit: Option<usize>) -> Self {
+        self.sample_matches = limit;
+        self
+    }
+
+    fn record_sample(&mut self, rule_name: &str, value: &str) {
+        let Some(limit) = self.sample_matches else {
+            return;
+        };
+        let Some(stats) = self.trace_stats.get_mut(rule_name) else {
+            return;
+        };
+        if stats.sample_matches.len() < limit {
+            let sample = match_shape(value);
+            if !stats.sample_matches.contains(&sample) {
+                stats.sample_matches.push(sample);
+            }
+        }
+    }
+
+    pub fn with_deterministic_trace_ids(mut self, deterministic: bool) -> Self {
+        self.deterministic_trace_ids = deterministic;
+        self
+    }
+
+    // Clock-based by default; deterministic mode makes identical inputs replay to identical IDs
+    fn next_trace_id(&mut self, rule_name: &str, value: &str) -> String {
+        if !self.deterministic_trace_ids {
+            return generate_trace_id(self.clock.now());
+        }
+        let occurrence = self
+            .occurrences
+            .entry((rule_name.to_string(), trace_hash(&[value])))
+            .or_insert(0);
+        let trace_id = deterministic_trace_id(rule_name, value, *occurrence);
+        *occurrence += 1;
+        trace_id
+    }
+
+    // The ID the next match of `value` would get, without consuming it
+    fn peek_trace_id(&self, rule_name: &str, value: &str) -> String {
+        if !self.deterministic_trace_ids {
+            return generate_trace_id(self.clock.now());
+        }
+        let occurrence = self
+            .occurrences
+            .get(&(rule_name.to_string(), trace_hash(&[value])))
+            .copied()
+            .unwrap_or(0);
+        deterministic_trace_id(rule_name, value, occurrence)
+    }
+
+    /// Limit tagged rules to those sharing a tag with `tags`; untagged rules always run.
+    /// `None` runs every rule.
+    pub fn set_active_tags(&mut self, tags: Option<Vec<String>>) {
+        self.active_tags = tags;
+    }
+
+    pub fn active_tags(&self) -> Option<&[String]> {
+        self.active_tags.as_deref()
+    }
+
+    fn rule_active(&self, rule: &CompiledTraceRule) -> bool {
+        match &self.active_tags {
+            Some(active) if !rule.tags.is_empty() => {
+                rule.tags.iter().any(|tag| active.contains(tag))
+            }
+            _ => true,
+        }
+    }
+
+    // Whether `rule_name` is a Detect rule, whose events leave the text as it was
+    pub(crate) fn detects_only(&self, rule_name: &str) -> bool {
+        self.compiled_rules
+            .iter()
+            .any(|rule| rule.name == rule_name && matches!(rule.method, ObfuscationMethod::Detect))
+    }
+
+    /// Whether any call since the last `take_truncated` hit the per-line match cap
+    pub fn take_truncated(&mut self) -> bool {
+        std::mem::take(&mut self.truncated)
+    }
+
+    // Allowlist patterns are anchored: they exempt a value only when they match all of it
+    pub fn with_allowlist(
+        mut self,
+        values: &[String],
+        patterns: &[String],
+    ) -> Result<Self, PhantomError> {
+        self.allowlist = values.iter().cloned().collect();
+        self.allowlist_patterns = patterns
+            .iter()
+            .map(|pattern| Regex::new(&format!("^(?:{})$", pattern)))
+            .collect::<Result<_, _>>()
+            .map_err(|source| PhantomError::InvalidRegex {
+                rule: "allowlist".to_string(),
+                source,
+            })?;
+        Ok(self)
+    }
+
+    /// Keys (full-match, case-insensitive regexes) whose values are obfuscated whole
+    /// with `method` by `redact_value`, whatever they contain
+    pub fn with_key_redaction(
+        mut self,
+        keys: &[String],
+        method: ObfuscationMethod,
+    ) -> Result<Self, PhantomError> {
+        if keys.is_empty() {
+            return Ok(self);
+        }
+        self.redact_keys = keys
+            .iter()
+            .map(|key| Regex::new(&format!("(?i)^(?:{})$", key)))
+            .collect::<Result<_, _>>()
+            .map_err(|source| PhantomError::InvalidRegex {
+                rule: "redact_keys".to_string(),
+                source,
+            })?;
+        self.key_redaction = Some(self.whole_value_rule(KEY_REDACTION_RULE, method)?);
+        Ok(self)
+    }
+
+    /// Let `redact_url_param` obfuscate whole query parameter values with `method`
+    pub fn with_url_param_redaction(
+        mut self,
+        enabled: bool,
+        method: ObfuscationMethod,
+    ) -> Result<Self, PhantomError> {
+        if enabled {
+            self.url_param_redaction = Some(self.whole_value_rule(URL_PARAM_RULE, method)?);
+        }
+        Ok(self)
+    }
+
+    // A rule matching any whole value, for redaction selected by a key or parameter name
+    fn whole_value_rule(
+        &mut self,
+        name: &str,
+        method: ObfuscationMethod,
+    ) -> Result<Arc<CompiledTraceRule>, PhantomError> {
+        let rule = TraceRule {
+            name: name.to_string(),
+            pattern: r"(?s).+".to_string(),
+            replacement: matches!(method, ObfuscationMethod::Mask)
+                .then(|| "[REDACTED]".to_string()),
+            method: Some(method),
+            severity: TraceSeverity::High,
+            ..Default::default()
+        };
+        let compiled = CompiledTraceRule::compile(&rule, true, true, &RegexLimits::default())?;
+        self.trace_stats.insert(
+            rule.name,
+            TraceStats {
+                severity_level: format!("{:?}", rule.severity),
+                ..Default::default()
+            },
+        );
+        Ok(Arc::new(compiled))
+    }
+
+    pub fn redacts_key(&self, key: &str) -> bool {
+        self.redact_keys.iter().any(|pattern| pattern.is_match(key))
+    }
+
+    /// Obfuscate all of `value` with the key-redaction method. `None` when key redaction
+    /// isn't configured or the value is empty.
+    pub fn redact_value(&mut self, value: &str) -> Option<(String, PhantomEvent)> {
+        let rule = Arc::clone(self.key_redaction.as_ref()?);
+        self.redact_whole(&rule, value)
+    }
 
-            // Update statistics if changes were made (FIXED: Use saturating_sub to prevent overflow)
-            if result != original_result {
-                let stats = self.trace_stats.get_mut(&rule.name).unwrap();
-                stats.phantoms_created += 1;
+    /// Obfuscate all of a decoded URL query parameter value. `None` when URL redaction
+    /// isn't enabled or the value is empty.
+    pub fn redact_url_param(&mut self, value: &str) -> Option<(String, PhantomEvent)> {
+        let rule = Arc::clone(self.url_param_redaction.as_ref()?);
+        self.redact_whole(&rule, value)
+    }
+
+    fn redact_whole(
+        &mut self,
+        rule: &CompiledTraceRule,
+        value: &str,
+    ) -> Option<(String, PhantomEvent)> {
+        let caps = rule.regex.captures(value)?;
+        let trace_id = self.next_trace_id(&rule.name, value);
+        let phantom_value = self.apply_obfuscation(value, rule, &caps, &trace_id);
+
+        let mut event = PhantomEvent {
+            rule_name: rule.name.clone(),
+            severity: rule.severity.clone(),
+            original_value: value.to_string(),
+            phantom_value,
+            position: (0, value.len()),
+            trace_id,
+            field_path: None,
+            confidence: 1.0, // Selected by name, not by pattern
+            line_number: None,
+            column: None,
+        };
+        if let Some(replacement) = self
+            .obfuscation_hook
+            .as_ref()
+            .and_then(|hook| (hook.0)(&event))
+        {
+            event.phantom_value = replacement;
+        }
+        self.record_change(
+            &rule.name,
+            value.len() as u64,
+            event.phantom_value.len() as u64,
+        );
+        self.record_sample(&rule.name, value);
+        Some((event.phantom_value.clone(), event))
+    }
+
+    fn is_allowlisted(&self, value: &str) -> bool {
+        self.allowlist.contains(value)
+            || self
+                .allowlist_patterns
+                .iter()
+                .any(|pattern| pattern.is_match(value))
+    }
+
+    // Single pass over the original text. Rules claim spans in rank order (severity,
+    // then priority, then config order); a match overlapping a claimed span is dropped
+    // whole, so the higher-ranked rule wins and positions always refer to `text`.
+    // Claimed spans are frozen: no rule ever sees replacement output, so a mask run
+    // like `████` can't be matched again by a broader rule. Detect rules change nothing,
+    // so they report every match without claiming, and never shadow a redacting rule.
+    // Returns (rule index, captures) in text order, and whether a rule hit the match cap.
+    // A rule whose `requires` didn't all claim a match is dropped and the text claimed
+    // again without it, so its spans go to other rules; repeated until nothing changes.
+    fn claim_matches<'t>(&self, text: &'t str) -> (Vec<(usize, regex::Captures<'t>)>, bool) {
+        let mut unmet = HashSet::new();
+        loop {
+            let (claimed, truncated) = self.claim_matches_without(text, &unmet);
+            let fired: HashSet<&str> = claimed
+                .iter()
+                .map(|(index, _)| self.compiled_rules[*index].name.as_str())
+                .collect();
+            let newly_unmet: Vec<usize> = claimed
+                .iter()
+                .map(|(index, _)| *index)
+                .filter(|&index| {
+                    let requires = &self.compiled_rules[index].requires;
+                    !requires.iter().all(|name| fired.contains(name.as_str()))
+                })
+                .collect();
+            if newly_unmet.is_empty() {
+                return (claimed, truncated);
+            }
+            unmet.extend(newly_unmet);
+        }
+    }
 
-                // Use saturating_sub to prevent subtraction overflow panics
-                stats.characters_traced +=
-                    (original_result.len() as u64).saturating_sub(result.len() as u64);
+    fn claim_matches_without<'t>(
+        &self,
+        text: &'t str,
+        excluded: &HashSet<usize>,
+    ) -> (Vec<(usize, regex::Captures<'t>)>, bool) {
+        let mut truncated = false;
+        let mut claimed_spans: BTreeMap<usize, usize> = BTreeMap::new();
+        let mut claimed = Vec::new();
+        for (index, rule) in self.compiled_rules.iter().enumerate() {
+            if !self.rule_active(rule) || excluded.contains(&index) {
+                continue;
+            }
+            let mut matches = rule.regex.captures_iter(text);
+            // Cap matches per rule so a crafted line can't expand without bound
+            let candidates: Vec<_> = match self.max_matches_per_line {
+                Some(cap) => {
+                    let taken = matches.by_ref().take(cap).collect();
+                    if matches.next().is_some() {
+                        truncated = true;
+                    }
+                    taken
+                }
+                None => matches.collect(),
+            };
 
-                let now = std::time::SystemTime::now();
-                if stats.first_trace.is_none() {
-                    stats.first_trace = Some(now);
+            for caps in candidates {
+                let Some(full) = caps.get(0) else { continue };
+                let (leading, matched, _) = rule.split_trimmed(full.as_str());
+                let start = full.start() + leading.len();
+                if self.skip_reason(rule, matched, text, start).is_some() {
+                    continue;
+                }
+                if matches!(rule.method, ObfuscationMethod::Detect) {
+                    claimed.push((index, caps));
+                    continue;
                 }
-                stats.last_trace = Some(now);
+                // Claimed spans are disjoint, so only the last one starting before our end
+                // can overlap us
+                let overlaps = claimed_spans
+                    .range(..full.end())
+                    .next_back()
+                    .is_some_and(|(_, &end)| end > full.start());
+                if overlaps {
+                    continue;
+                }
+                claimed_spans.insert(full.start(), full.end());
+                claimed.push((index, caps));
             }
         }
+        claimed.sort_by_key(|(_, caps)| caps.get(0).map_or(0, |m| m.start()));
+        (claimed, truncated)
+    }
+
+    pub fn trace_and_phantom(&mut self, text: &str) -> (String, Vec<PhantomEvent>) {
+        let rules = Arc::clone(&self.compiled_rules);
+        let hook = self.obfuscation_hook.clone();
+        let (claimed, truncated) = self.claim_matches(text);
+        self.truncated |= truncated;
+
+        let mut result = String::with_capacity(text.len());
+        let mut events = Vec::with_capacity(claimed.len());
+        let mut cursor = 0;
+        // Per rule: bytes matched and bytes written, for rules that changed the text
+        let mut rule_changes: BTreeMap<usize, (u64, u64)> = BTreeMap::new();
+
+        for (index, caps) in claimed {
+            let rule = &rules[index];
+            let Some(full) = caps.get(0) else { continue };
+            let (leading, matched, trailing) = rule.split_trimmed(full.as_str());
+            let start = full.start() + leading.len();
+            let trace_id = self.next_trace_id(&rule.name, matched);
+            let phantomed = rule.obfuscate_parts(matched, start, &caps, |part| {
+                self.apply_obfuscation(part, rule, &caps, &trace_id)
+            });
+
+            // Record the phantom event
+            let mut event = PhantomEvent {
+                rule_name: rule.name.clone(),
+                severity: rule.severity.clone(),
+                original_value: matched.to_string(),
+                phantom_value: phantomed,
+                position: (start, start + matched.len()),
+                trace_id,
+                field_path: None,
+                confidence: rule.confidence(matched),
+                line_number: None,
+                column: None,
+            };
+            if let Some(replacement) = hook.as_ref().and_then(|hook| (hook.0)(&event)) {
+                event.phantom_value = replacement;
+            }
+            if rule.consistent {
+                let key = (rule.name.clone(), value_fingerprint(matched));
+                event.phantom_value = self.consistent_output(key, event.phantom_value);
+            }
+            // Unclaimed, so it may overlap a redacted span; the text is left to other rules
+            if matches!(rule.method, ObfuscationMethod::Detect) {
+                self.record_sample(&rule.name, matched);
+                events.push(event);
+                continue;
+            }
+
+            result.push_str(&text[cursor..full.start()]);
+            result.push_str(leading);
+            result.push_str(&event.phantom_value);
+            result.push_str(trailing);
+            cursor = full.end();
+
+            if event.phantom_value != matched {
+                let change = rule_changes.entry(index).or_default();
+                change.0 += matched.len() as u64;
+                change.1 += event.phantom_value.len() as u64;
+            }
+            self.record_sample(&rule.name, matched);
+            events.push(event);
+        }
+        result.push_str(&text[cursor..]);
+
+        // Update statistics for rules that changed the text
+        for (index, (matched_len, written_len)) in rule_changes {
+            self.record_change(&rules[index].name, matched_len, written_len);
+        }
 
         (result, events)
     }
 
+    fn record_change(&mut self, rule_name: &str, matched_len: u64, written_len: u64) {
+        let stats = self.trace_stats.get_mut(rule_name).unwrap();
+        stats.phantoms_created += 1;
+
+        // Use saturating_sub to prevent subtraction overflow panics
+        stats.characters_traced += matched_len.saturating_sub(written_len);
+
+        let now = self.clock.now();
+        if stats.first_trace.is_none() {
+            stats.first_trace = Some(now);
+        }
+        stats.last_trace = Some(now);
+    }
+
+    /// Whether `trace_and_phantom` would find anything in `text`. One `RegexSet` pass
+    /// rules out clean text; only rules it reports are run to apply length, context,
+    /// allowlist and confidence checks, and nothing is obfuscated or recorded.
+    pub fn contains_match(&self, text: &str) -> bool {
+        let rule_set = self.rule_set.get_or_init(|| {
+            RegexSet::new(self.compiled_rules.iter().map(|rule| rule.regex.as_str())).ok()
+        });
+        let candidates: Vec<usize> = match rule_set {
+            Some(rule_set) => rule_set.matches(text).into_iter().collect(),
+            None => (0..self.compiled_rules.len()).collect(),
+        };
+        candidates.into_iter().any(|index| {
+            let rule = &self.compiled_rules[index];
+            // A rule with `requires` applies only if its required rules do, and the
+            // chain ends at one without, which is checked here on its own
+            if !self.rule_active(rule) || !rule.requires.is_empty() {
+                return false;
+            }
+            let cap = self.max_matches_per_line.unwrap_or(usize::MAX);
+            // A match lost to an overlapping rule still means that rule matched
+            rule.regex.find_iter(text).take(cap).any(|found| {
+                let (leading, matched, _) = rule.split_trimmed(found.as_str());
+                let start = found.start() + leading.len();
+                self.skip_reason(rule, matched, text, start).is_none()
+            })
+        })
+    }
+
+    /// The events `trace_and_phantom` would produce for `text`, positions and phantom
+    /// values included, without recording stats, claiming tokens or advancing trace IDs.
+    /// A value repeated within `text` gets the same (next) deterministic trace ID each time.
+    pub fn find_events(&self, text: &str) -> Vec<PhantomEvent> {
+        let (claimed, _) = self.claim_matches(text);
+        claimed
+            .into_iter()
+            .filter_map(|(index, caps)| {
+                let rule = &self.compiled_rules[index];
+                let full = caps.get(0)?;
+                let (leading, matched, _) = rule.split_trimmed(full.as_str());
+                let start = full.start() + leading.len();
+                let trace_id = self.peek_trace_id(&rule.name, matched);
+                let mut event = PhantomEvent {
+                    rule_name: rule.name.clone(),
+                    severity: rule.severity.clone(),
+                    original_value: matched.to_string(),
+                    phantom_value: rule.obfuscate_parts(matched, start, &caps, |part| {
+                        self.obfuscated_value(part, rule, &caps, &trace_id)
+                    }),
+                    position: (start, start + matched.len()),
+                    trace_id,
+                    field_path: None,
+                    confidence: rule.confidence(matched),
+                    line_number: None,
+                    column: None,
+                };
+                if let Some(replacement) = self
+                    .obfuscation_hook
+                    .as_ref()
+                    .and_then(|hook| (hook.0)(&event))
+                {
+                    event.phantom_value = replacement;
+                }
+                if rule.consistent {
+                    let key = (rule.name.clone(), value_fingerprint(matched));
+                    if let Some(earlier) = self.consistent_outputs.get(&key) {
+                        event.phantom_value = earlier.clone();
+                    }
+                }
+                Some(event)
+            })
+            .collect()
+    }
+
+    /// What each rule would do to `text`, without touching stats or tokens.
+    /// Every match is listed, whereas `trace_and_phantom` drops a match that overlaps a span
+    /// already claimed by a higher-ranked rule.
+    pub fn explain(&self, text: &str) -> Vec<RuleMatchExplanation> {
+        // Rules that would fire, to tell which `requires` are met
+        let (claimed, _) = self.claim_matches(text);
+        let fired: HashSet<&str> = claimed
+            .iter()
+            .map(|(index, _)| self.compiled_rules[*index].name.as_str())
+            .collect();
+        self.compiled_rules
+            .iter()
+            .map(|rule| RuleMatchExplanation {
+                rule_name: rule.name.clone(),
+                severity: rule.severity.clone(),
+                method: rule.method.clone(),
+                matches: rule
+                    .regex
+                    .captures_iter(text)
+                    .map(|caps| {
+                        let full_match = caps.get(0).map_or("", |m| m.as_str());
+                        let (leading, matched, _) = rule.split_trimmed(full_match);
+                        let start = caps.get(0).map_or(0, |m| m.start()) + leading.len();
+                        let skipped = self.skip_reason(rule, matched, text, start).or_else(|| {
+                            let unmet = rule
+                                .requires
+                                .iter()
+                                .any(|name| !fired.contains(name.as_str()));
+                            unmet.then_some("required rule did not match")
+                        });
+                        let phantom_value = if skipped.is_some() {
+                            matched.to_string()
+                        } else {
+                            let trace_id = self.peek_trace_id(&rule.name, matched);
+                            let event = PhantomEvent {
+                                rule_name: rule.name.clone(),
+                                severity: rule.severity.clone(),
+                                original_value: matched.to_string(),
+                                phantom_value: rule.obfuscate_parts(
+                                    matched,
+                                    start,
+                                    &caps,
+                                    |part| self.obfuscated_value(part, rule, &caps, &trace_id),
+                                ),
+                                position: (start, start + matched.len()),
+                                trace_id,
+                                field_path: None,
+                                confidence: rule.confidence(matched),
+                                line_number: None,
+                                column: None,
+                            };
+                            self.obfuscation_hook
+                                .as_ref()
+                                .and_then(|hook| (hook.0)(&event))
+                                .unwrap_or(event.phantom_value)
+                        };
+                        MatchExplanation {
+                            span: (start, start + matched.len()),
+                            matched: matched.to_string(),
+                            phantom_value,
+                            skipped: skipped.map(str::to_string),
+                        }
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    // Why a match is left untouched, if it is
+    fn skip_reason(
+        &self,
+        rule: &CompiledTraceRule,
+        matched: &str,
+        haystack: &str,
+        start: usize,
+    ) -> Option<&'static str> {
+        if !self.rule_active(rule) {
+            Some("no active tag")
+        } else if matched.is_empty() {
+            Some("empty after trimming")
+        } else if !rule.accepts_length(matched) {
+            Some("outside min_length/max_length")
+        } else if rule.validator.is_some_and(|v| v.rejects(matched)) {
+            Some("rejected by validator")
+        } else if !rule.has_context(haystack, start) {
+            Some("no context keyword nearby")
+        } else if self.is_allowlisted(matched) {
+            Some("allowlisted")
+        } else if rule.confidence(matched) < self.min_confidence {
+            Some("below min_confidence")
+        } else {
+            None
+        }
+    }
+
     fn apply_obfuscation(
         &mut self,
         value: &str,
-        method: &ObfuscationMethod,
-        preserve_chars: Option<usize>,
-        replacement: &Option<String>,
+        rule: &CompiledTraceRule,
+        caps: &regex::Captures,
+        trace_id: &str,
     ) -> String {
-        match method {
+        if let Some(scope) = hash_scope(rule) {
+            self.register_hash(scope, value);
+        }
+        self.obfuscated_value(value, rule, caps, trace_id)
+    }
+
+    // Claim a hash for `value`. A distinct value already holding the hash is a collision:
+    // rehash with a counter suffix until a free (or our own) slot turns up.
+    fn register_hash(&mut self, scope: &str, value: &str) {
+        let fingerprint = value_fingerprint(value);
+        let mut hash = phantom_hash(value);
+        // Held across the probe so concurrent sessions can't claim the same slot
+        let mut issued_hashes = self.issued_hashes();
+        for attempt in 1.. {
+            match issued_hashes.get(&(scope.to_string(), hash)) {
+                Some(&owner) if owner == fingerprint => return,
+                Some(_) => hash = phantom_hash(&format!("{}\0{}", value, attempt)),
+                None => {
+                    if attempt > 1 {
+                        eprintln!(
+                            "Phantom hash collision in '{}' resolved after {} rehash(es)",
+                            scope,
+                            attempt - 1
+                        );
+                    }
+                    issued_hashes.insert((scope.to_string(), hash), fingerprint);
+                    return;
+                }
+            }
+        }
+    }
+
+    // The hash `register_hash` gave (or would give) `value`, without claiming it
+    fn issued_hash(&self, scope: &str, value: &str) -> u64 {
+        let fingerprint = value_fingerprint(value);
+        let mut hash = phantom_hash(value);
+        let issued_hashes = self.issued_hashes();
+        for attempt in 1.. {
+            match issued_hashes.get(&(scope.to_string(), hash)) {
+                Some(&owner) if owner != fingerprint => {
+                    hash = phantom_hash(&format!("{}\0{}", value, attempt))
+                }
+                _ => break,
+            }
+        }
+        hash
+    }
+
+    fn obfuscated_value(
+        &self,
+        value: &str,
+        rule: &CompiledTraceRule,
+        caps: &regex::Captures,
+        trace_id: &str,
+    ) -> String {
+        let mask_char = rule.mask_char.unwrap_or(self.mask_char);
+        match &rule.method {
             ObfuscationMethod::Phantom => {
-                let preserve = preserve_chars.unwrap_or(0);
-                phantom_string(value, preserve)
+                let preserve = rule.preserve_chars.unwrap_or(0);
+                if self.grapheme_masking {
+                    phantom_graphemes(value, preserve, mask_char)
+                } else {
+                    phantom_string(value, preserve, mask_char)
+                }
             }
             ObfuscationMethod::Mirror => {
-                format!("PHANTOM_{:08X}", phantom_hash(value))
-            }
-            ObfuscationMethod::Mask => replacement
-                .clone()
-                .unwrap_or_else(|| "[PHANTOMED]".to_string()),
-            ObfuscationMethod::Vanish => String::new(),
-            ObfuscationMethod::Tokenize => {
-                // Consistent tokenization
-                let token_key = format!("token_{}", phantom_hash(value));
-                if let Some(existing_token) = self.phantom_tokens.get(&token_key) {
-                    existing_token.clone()
+                let hash = self.issued_hash(MIRROR_SCOPE, value);
+                let token = match &rule.token_format {
+                    Some(format) => render_token(format, hash, rule),
+                    None => format!("PHANTOM_{:016X}", hash),
+                };
+                if rule.mirror_preserve_length {
+                    fit_token_length(&token, hash, value.chars().count())
                 } else {
-                    let token = format!("PHANTOM_TOKEN_{:08X}", phantom_hash(value));
-                    self.phantom_tokens.insert(token_key, token.clone());
                     token
                 }
             }
+            ObfuscationMethod::Mask => match &rule.replacement {
+                Some(template) => render_replacement(template, value, rule, caps, trace_id),
+                None => "[PHANTOMED]".to_string(),
+            },
+            // Optional fixed marker so removal can't fuse the neighbouring tokens;
+            // `{mask:len}` fills the original width for fixed-width formats
+            ObfuscationMethod::Vanish => match &rule.replacement {
+                Some(marker) => marker.replace("{mask:len}", &phantom_string(value, 0, mask_char)),
+                None => String::new(),
+            },
+            ObfuscationMethod::Phone => phantom_phone(value, mask_char),
+            ObfuscationMethod::Card => {
+                phantom_card(value, rule.preserve_chars.unwrap_or(4), mask_char)
+            }
+            ObfuscationMethod::Detect => value.to_string(),
+            // Scoped per rule so categories never share a token
+            ObfuscationMethod::Tokenize => {
+                let hash = self.issued_hash(&rule.name, value);
+                match &rule.token_format {
+                    Some(format) => render_token(format, hash, rule),
+                    None => format!("PHANTOM_{}_{:016X}", token_namespace(&rule.name), hash),
+                }
+            }
         }
     }
 
     pub fn get_trace_report(&self) -> TraceReport {
         let mut total_phantoms_created = 0;
         let mut total_characters_traced = 0;
-        let mut severity_breakdown = HashMap::new();
+        let mut severity_breakdown = BTreeMap::new();
 
         for stats in self.trace_stats.values() {
             total_phantoms_created += stats.phantoms_created;
@@ -190,14 +1115,18 @@ impl PhantomTracer {
             .filter(|s| s.phantoms_created > 0)
             .count();
 
-        let generation_time = std::time::SystemTime::now();
+        let generation_time = self.clock.now();
 
         TraceReport {
             total_phantoms_created,
             total_characters_traced,
             rules_triggered,
             severity_breakdown,
-            detailed_stats: self.trace_stats.clone(),
+            detailed_stats: self
+                .trace_stats
+                .iter()
+                .map(|(rule_name, stats)| (rule_name.clone(), stats.clone()))
+                .collect(),
             generation_time,
         }
     }
@@ -209,7 +1138,56 @@ impl PhantomTracer {
                 ..Default::default()
             };
         }
-        self.phantom_tokens.clear();
+        // A shared map belongs to every session, so it is never reset from one of them
+        if !self.shared_tokens {
+            self.issued_hashes = Arc::default();
+        }
+        self.occurrences.clear();
+        self.consistent_outputs.clear();
+        self.consistent_order.clear();
+    }
+
+    // The output first given for `key`, else `phantom_value`, remembered from now on.
+    // Past `MAX_CONSISTENT_OUTPUTS` the oldest value is forgotten to make room.
+    fn consistent_output(&mut self, key: (String, u64), phantom_value: String) -> String {
+        if let Some(earlier) = self.consistent_outputs.get(&key) {
+            return earlier.clone();
+        }
+        if self.consistent_outputs.len() >= MAX_CONSISTENT_OUTPUTS {
+            if let Some(oldest) = self.consistent_order.pop_front() {
+                self.consistent_outputs.remove(&oldest);
+            }
+        }
+        self.consistent_order.push_back(key.clone());
+        self.consistent_outputs.insert(key, phantom_value.clone());
+        phantom_value
+    }
+
+    /// Zero one rule's stats, leaving other rules and the token map alone.
+    /// Returns whether the tracer has a rule called `rule_name`.
+    pub fn reset_rule_stats(&mut self, rule_name: &str) -> bool {
+        let Some(stats) = self.trace_stats.get_mut(rule_name) else {
+            return false;
+        };
+        *stats = TraceStats {
+            severity_level: stats.severity_level.clone(),
+            ..Default::default()
+        };
+        true
+    }
+
+    /// A tracer with fresh stats that shares this one's compiled rules, so it costs
+    /// no regex compilation. Tokens are fresh too, unless the token map is shared.
+    pub fn session(&self) -> Self {
+        let mut session = self.clone();
+        session.reset_traces();
+        session.truncated = false;
+        session
+    }
+
+    #[cfg(test)]
+    pub(crate) fn issued_token_count(&self) -> usize {
+        self.issued_hashes().len()
     }
 }
 
@@ -217,25 +1195,115 @@ impl PhantomTracer {
 pub struct PhantomEvent {
     pub rule_name: String,
     pub severity: TraceSeverity,
+    #[serde(skip_serializing_if = "String::is_empty")] // Emptied by `output.hash_original_values`
     pub original_value: String,
     pub phantom_value: String,
     pub position: (usize, usize),
     pub trace_id: String,
+    // JSON-aware mode with `preserve_structure`: where the value lived, e.g. `user.contact.email`.
+    // `position` is then relative to that value.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field_path: Option<String>,
+    pub confidence: f32, // 0.0-1.0, from the rule's validator and context keywords
+    // With `output.event_line_columns`: 1-based line of multi-line input where the match
+    // starts, and its 1-based column counted in chars. `position` is unchanged.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_number: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+/// Pattern compilation timings, for startup profiling; see `PhantomTracer::compile_stats`
+#[derive(Debug, Clone)]
+pub struct CompileStats {
+    pub rules: Vec<RuleCompileTime>,
+    pub total: std::time::Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleCompileTime {
+    pub rule_name: String,
+    pub duration: std::time::Duration,
+}
+
+/// Every match one rule finds in a sample text; see `PhantomTracer::explain`
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleMatchExplanation {
+    pub rule_name: String,
+    pub severity: TraceSeverity,
+    pub method: ObfuscationMethod,
+    pub matches: Vec<MatchExplanation>,
 }
 
 #[derive(Debug, Clone, Serialize)]
+pub struct MatchExplanation {
+    pub span: (usize, usize), // Byte offsets into the explained text
+    pub matched: String,
+    pub phantom_value: String,
+    pub skipped: Option<String>, // Why the match would be left as-is
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TraceReport {
     pub total_phantoms_created: u64,
     pub total_characters_traced: u64,
     pub rules_triggered: usize,
-    pub severity_breakdown: HashMap<String, u64>,
-    pub detailed_stats: HashMap<String, TraceStats>,
+    // Ordered maps, so serialized reports are byte-for-byte reproducible
+    pub severity_breakdown: BTreeMap<String, u64>,
+    pub detailed_stats: BTreeMap<String, TraceStats>,
+    #[serde(with = "crate::rfc3339")]
     pub generation_time: std::time::SystemTime,
 }
 
+impl TraceReport {
+    /// Fold another worker's report into this one, e.g. to build a fleet-wide view.
+    /// Counts are summed, per-rule trace times widened and samples unioned;
+    /// `generation_time` becomes the later of the two.
+    pub fn merge(&mut self, other: &TraceReport) {
+        self.total_phantoms_created += other.total_phantoms_created;
+        self.total_characters_traced += other.total_characters_traced;
+        for (severity, count) in &other.severity_breakdown {
+            *self.severity_breakdown.entry(severity.clone()).or_insert(0) += count;
+        }
+        for (rule_name, theirs) in &other.detailed_stats {
+            match self.detailed_stats.get_mut(rule_name) {
+                Some(ours) => ours.merge(theirs),
+                None => {
+                    self.detailed_stats
+                        .insert(rule_name.clone(), theirs.clone());
+                }
+            }
+        }
+        self.rules_triggered = self
+            .detailed_stats
+            .values()
+            .filter(|s| s.phantoms_created > 0)
+            .count();
+        self.generation_time = self.generation_time.max(other.generation_time);
+    }
+}
+
+impl TraceStats {
+    fn merge(&mut self, other: &TraceStats) {
+        self.phantoms_created += other.phantoms_created;
+        self.characters_traced += other.characters_traced;
+        self.first_trace = match (self.first_trace, other.first_trace) {
+            (Some(ours), Some(theirs)) => Some(ours.min(theirs)),
+            (ours, theirs) => ours.or(theirs),
+        };
+        self.last_trace = self.last_trace.max(other.last_trace);
+        for sample in &other.sample_matches {
+            if !self.sample_matches.contains(sample) {
+                self.sample_matches.push(sample.clone());
+            }
+        }
+    }
+}
+
 // Utility functions for phantoming
-fn phantom_string(input: &str, preserve: usize) -> String {
-    let len = input.len();
+// Lengths and preserved counts are in chars, so output length always equals input length
+pub(crate) fn phantom_string(input: &str, preserve: usize, mask_char: char) -> String {
+    let len = input.chars().count();
 
     // Handle empty strings
     if len == 0 {
@@ -244,42 +1312,294 @@ fn phantom_string(input: &str, preserve: usize) -> String {
 
     // If preserve is 0, replace everything
     if preserve == 0 {
-        return "█".repeat(len);
+        return mask_char.to_string().repeat(len);
     }
 
     // If total preservation (both sides) is greater than or equal to length,
     // replace entire string with block characters
     if preserve.saturating_mul(2) >= len {
-        return "█".repeat(len);
+        return mask_char.to_string().repeat(len);
     }
 
-    // Safe to slice since we've verified lengths
-    format!(
-        "{}{}{}",
-        &input[..preserve],
-        "█".repeat(len - preserve * 2),
-        &input[len - preserve..]
-    )
+    input
+        .chars()
+        .enumerate()
+        .map(|(index, c)| {
+            if index < preserve || index >= len - preserve {
+                c
+            } else {
+                mask_char
+            }
+        })
+        .collect()
+}
+
+// `phantom_string` over grapheme clusters: one mask char per masked cluster
+pub(crate) fn phantom_graphemes(input: &str, preserve: usize, mask_char: char) -> String {
+    let graphemes: Vec<&str> = input.graphemes(true).collect();
+    let len = graphemes.len();
+    if preserve == 0 || preserve.saturating_mul(2) >= len {
+        return mask_char.to_string().repeat(len);
+    }
+
+    graphemes
+        .iter()
+        .enumerate()
+        .map(|(index, grapheme)| {
+            if index < preserve || index >= len - preserve {
+                grapheme.to_string()
+            } else {
+                mask_char.to_string()
+            }
+        })
+        .collect()
+}
+
+// Any punctuation style (`555.123.4567`, `+1 555 123 4567`, ...) comes out in the same
+// `(███) ███-4567` shape, revealing only the last four digits
+pub(crate) fn phantom_phone(input: &str, mask_char: char) -> String {
+    let digits: Vec<char> = input.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 4 {
+        return phantom_string(input, 0, mask_char);
+    }
+
+    let last4: String = digits[digits.len() - 4..].iter().collect();
+    let masked = mask_char.to_string().repeat(3);
+    format!("({}) {}-{}", masked, masked, last4)
+}
+
+// Mask every digit but the last `preserve`; separators keep their place, so
+// `4532-0151-1283-0366` becomes `****-****-****-0366`
+pub(crate) fn phantom_card(input: &str, preserve: usize, mask_char: char) -> String {
+    let digits = input.chars().filter(|c| c.is_ascii_digit()).count();
+    // Revealing at least half the digits would leave too little hidden
+    let keep_from = if preserve.saturating_mul(2) >= digits {
+        digits
+    } else {
+        digits - preserve
+    };
+
+    let mut seen = 0;
+    input
+        .chars()
+        .map(|c| {
+            if !c.is_ascii_digit() {
+                return c;
+            }
+            seen += 1;
+            if seen > keep_from {
+                c
+            } else {
+                mask_char
+            }
+        })
+        .collect()
+}
+
+// Fill `{placeholder}`s in a Mask replacement. Unknown placeholders are kept verbatim,
+// so plain replacements that happen to contain braces are unaffected.
+fn render_replacement(
+    template: &str,
+    value: &str,
+    rule: &CompiledTraceRule,
+    caps: &regex::Captures,
+    trace_id: &str,
+) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        let Some(close) = rest[open..].find('}') else {
+            break;
+        };
+        rendered.push_str(&rest[..open]);
+        let placeholder = &rest[open + 1..open + close];
+        let substitution = match placeholder {
+            "last4" => Some(chars[chars.len().saturating_sub(4)..].iter().collect()),
+            "first2" => Some(chars.iter().take(2).collect()),
+            "len" => Some(chars.len().to_string()),
+            "rule" => Some(rule.name.clone()),
+            "severity" => Some(format!("{:?}", rule.severity)),
+            "trace_id" => Some(trace_id.to_string()),
+            group => match group.parse::<usize>() {
+                Ok(index) => caps.get(index).map(|m| m.as_str().to_string()),
+                Err(_) => caps.name(group).map(|m| m.as_str().to_string()),
+            },
+        };
+        match substitution {
+            Some(text) => rendered.push_str(&text),
+            None => rendered.push_str(&rest[open..=open + close]),
+        }
+        rest = &rest[open + close + 1..];
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+const SAMPLE_MAX_CHARS: usize = 32;
+
+// What a match looked like without what it said: letters become `a`, digits `9`,
+// punctuation stays, and long values are cut short
+fn match_shape(value: &str) -> String {
+    let mut shape: String = value
+        .chars()
+        .take(SAMPLE_MAX_CHARS)
+        .map(|c| {
+            if c.is_alphabetic() {
+                'a'
+            } else if c.is_numeric() {
+                '9'
+            } else {
+                c
+            }
+        })
+        .collect();
+    if value.chars().count() > SAMPLE_MAX_CHARS {
+        shape.push('…');
+    }
+    shape
+}
+
+// `{hash}` becomes the 16-digit hex hash, `{rule}` the rule name
+fn render_token(format: &str, hash: u64, rule: &CompiledTraceRule) -> String {
+    format
+        .replace("{hash}", &format!("{:016X}", hash))
+        .replace("{rule}", &rule.name)
+}
+
+// Truncate to the token's last `len` characters (where the hash digits sit in the
+// default format), or pad with further hex digits derived from `hash`
+fn fit_token_length(token: &str, hash: u64, len: usize) -> String {
+    let token_len = token.chars().count();
+    if token_len >= len {
+        return token.chars().skip(token_len - len).collect();
+    }
+    let mut fitted = token.to_string();
+    let mut block = hash;
+    while fitted.chars().count() < len {
+        block = phantom_hash(&format!("{:016X}", block));
+        fitted.push_str(&format!("{:016X}", block));
+    }
+    fitted.chars().take(len).collect()
+}
+
+// Rule name as it appears inside a token, e.g. "credit_card" -> "CREDIT_CARD"
+fn token_namespace(rule_name: &str) -> String {
+    rule_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
 }
 
-fn phantom_hash(input: &str) -> u32 {
+// Rewrite `\b`/`\B` as their ASCII-only forms. Escaped backslashes and the `\b{start}`
+// family are left alone.
+fn ascii_word_boundaries(pattern: &str) -> String {
+    let mut rewritten = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            rewritten.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(boundary @ ('b' | 'B')) if chars.peek() != Some(&'{') => {
+                rewritten.push_str("(?-u:\\");
+                rewritten.push(boundary);
+                rewritten.push(')');
+            }
+            Some(escaped) => {
+                rewritten.push('\\');
+                rewritten.push(escaped);
+            }
+            None => rewritten.push('\\'),
+        }
+    }
+    rewritten
+}
+
+// Rank rules for the single-pass matcher, which lets earlier rules claim spans first
+fn rank_rules(rules: &mut [CompiledTraceRule], order: &RuleOrder) {
+    match order {
+        RuleOrder::Severity => rules.sort_by(|a, b| {
+            b.severity
+                .cmp(&a.severity)
+                .then(b.priority.cmp(&a.priority))
+                .then(a.declaration.cmp(&b.declaration))
+        }),
+        RuleOrder::Priority => rules.sort_by(|a, b| {
+            b.priority
+                .cmp(&a.priority)
+                .then(a.declaration.cmp(&b.declaration))
+        }),
+        RuleOrder::Declaration => rules.sort_by_key(|rule| rule.declaration),
+    }
+}
+
+// Mirror values are shared across rules, so they all draw from one scope
+const MIRROR_SCOPE: &str = "";
+
+fn hash_scope(rule: &CompiledTraceRule) -> Option<&str> {
+    match rule.method {
+        ObfuscationMethod::Mirror => Some(MIRROR_SCOPE),
+        ObfuscationMethod::Tokenize => Some(&rule.name),
+        _ => None,
+    }
+}
+
+// 64 bits keeps accidental collisions out of reach of any realistic log volume
+// (32 bits hit the birthday bound around 65k distinct values)
+pub(crate) fn phantom_hash(input: &str) -> u64 {
     // Simple but effective hash function (not cryptographic)
-    let mut hash = 2166136261u32;
+    let mut hash = 0xcbf29ce484222325u64;
     for byte in input.bytes() {
-        hash ^= byte as u32;
-        hash = hash.wrapping_mul(16777619);
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Independent of `phantom_hash`, so two values sharing one are told apart by the other
+fn value_fingerprint(value: &str) -> u64 {
+    trace_hash(&["fingerprint", value])
+}
+
+fn deterministic_trace_id(rule_name: &str, value: &str, occurrence: u64) -> String {
+    format!(
+        "TRACE_{:016X}",
+        trace_hash(&[rule_name, value, &occurrence.to_string()])
+    )
+}
+
+// 64-bit FNV-1a over the parts, separated so ("ab", "c") and ("a", "bc") differ
+fn trace_hash(parts: &[&str]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for part in parts {
+        for byte in part.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
     }
     hash
 }
 
-fn generate_trace_id() -> String {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
+fn generate_trace_id(now: std::time::SystemTime) -> String {
+    use std::time::UNIX_EPOCH;
+    let timestamp = now.duration_since(UNIX_EPOCH).unwrap().as_nanos();
     format!("TRACE_{:016X}", timestamp & 0xFFFFFFFFFFFFFFFF)
 }
 
+use crate::clock::{Clock, SystemClock};
 // Re-export types from config
-use crate::config::{ObfuscationMethod, TraceRule, TraceSeverity};
+use crate::config::{
+    default_mask_char, MatchValidator, ObfuscationMethod, RegexLimits, RuleOrder, TraceRule,
+    TraceSeverity,
+};
+use crate::error::PhantomError;
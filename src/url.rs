@@ -0,0 +1,88 @@
+//! Query parameters of `http(s)://` URLs embedded in free text
+
+use regex::Regex;
+use std::sync::LazyLock;
+
+// Ends at whitespace, quotes and angle brackets, which can't appear unescaped in a URL
+static URL: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)\bhttps?://[^\s"'<>]+"#).expect("URL pattern is valid"));
+
+/// One `name=value` query parameter, percent-decoded
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryParam {
+    pub name: String,
+    pub value: String,
+    pub span: (usize, usize), // Byte range of the still-encoded value in the text
+}
+
+/// Every query parameter with a non-empty value, across all URLs in `text`, in order
+pub fn query_params(text: &str) -> Vec<QueryParam> {
+    let mut params = Vec::new();
+    for url in URL.find_iter(text) {
+        let Some(query_start) = url.as_str().find('?') else {
+            continue;
+        };
+        let query = &url.as_str()[query_start + 1..];
+        let query = query.split('#').next().unwrap_or_default();
+
+        let mut offset = url.start() + query_start + 1;
+        for param in query.split('&') {
+            if let Some((name, value)) = param.split_once('=') {
+                if !value.is_empty() {
+                    let value_start = offset + name.len() + 1;
+                    params.push(QueryParam {
+                        name: percent_decode(name),
+                        value: percent_decode(value),
+                        span: (value_start, value_start + value.len()),
+                    });
+                }
+            }
+            offset += param.len() + 1;
+        }
+    }
+    params
+}
+
+/// Decode `%XX` escapes and `+` (a space in query strings). Malformed escapes are kept
+/// as written, and byte sequences that aren't UTF-8 are decoded lossily.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let escape = text
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(byte) = escape {
+                    decoded.push(byte);
+                    i += 2;
+                } else {
+                    decoded.push(b'%');
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape only what would change how the query splits or where the URL ends, so a
+/// phantom value like `[REDACTED]` or `████` stays readable and one query value
+pub fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii_control() || c.is_whitespace() || "%&#+=\"'<>".contains(c) {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            encoded.push(c);
+        }
+    }
+    encoded
+}
@@ -0,0 +1,20 @@
+//! Browser entry points, built with the `wasm` feature:
+//! `cargo build --lib --target wasm32-unknown-unknown --features wasm`
+
+use crate::config::PhantomTraceConfig;
+use crate::processor::PhantomTraceProcessor;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    // Rules are compiled once; each call gets a fresh session so nothing accumulates
+    static TEMPLATE: PhantomTraceProcessor =
+        PhantomTraceProcessor::new(PhantomTraceConfig::default())
+            .expect("default rules compile");
+}
+
+/// Phantom `input` with the default rules, e.g. to scrub form data before it leaves
+/// the page. Tokens are not shared between calls.
+#[wasm_bindgen]
+pub fn phantom_text_wasm(input: &str) -> String {
+    TEMPLATE.with(|template| template.session().phantom_text(input).phantomed_text)
+}